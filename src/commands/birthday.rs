@@ -1,14 +1,32 @@
-use crate::RedisConnectionKey;
+use crate::{
+  util::{
+    checkpoint::{advance_field_checkpoint, release_field_checkpoint},
+    leader::Lease,
+    scheduler::{RoleRemoval, Scheduler},
+  },
+  RedisConnectionKey, RedisWrapper,
+};
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Arc,
+};
 
-use chrono::NaiveDate;
-use redis::{AsyncCommands, RedisError};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::{Tz, EST5EDT};
+use redis::{aio::Connection, pipe, AsyncCommands, RedisResult};
 use serenity::{
   builder::CreateApplicationCommands,
-  model::application::{
-    command::*,
-    interaction::{application_command::*, *},
+  http::Http,
+  model::{
+    application::{
+      command::*,
+      interaction::{application_command::*, *},
+    },
+    channel::ChannelType,
+    id::{ChannelId, UserId},
   },
   prelude::*,
+  utils::Color,
 };
 
 use super::util::get_str_or_error;
@@ -21,10 +39,125 @@ pub fn birthday_command(
       .name("birthday")
       .description("Set your birthday for getting a notification")
       .create_option(|op| {
-        op.name("date")
-          .kind(CommandOptionType::String)
-          .description("Your birthday (in m/d/y), or None to remove")
-          .required(true)
+        op.name("set")
+          .kind(CommandOptionType::SubCommand)
+          .description("Set your birthday, and have it announced in this server")
+          .create_sub_option(|date| {
+            date
+              .name("date")
+              .kind(CommandOptionType::String)
+              .description("Your birthday (in m/d/y, or m/d to leave out the year)")
+              .required(true)
+          })
+          .create_sub_option(|hide| {
+            hide
+              .name("hide_age")
+              .kind(CommandOptionType::Boolean)
+              .description("Don't show your age when announcing your birthday")
+              .required(false)
+          })
+          .create_sub_option(|leap| {
+            leap
+              .name("leap_day")
+              .kind(CommandOptionType::String)
+              .description("If you were born on Feb 29, when to celebrate in other years")
+              .add_string_choice("Feb 28", LeapDay::Feb28.as_str())
+              .add_string_choice("Mar 1", LeapDay::Mar1.as_str())
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("remove")
+          .kind(CommandOptionType::SubCommand)
+          .description("Stop announcing your birthday in this server")
+          .create_sub_option(|everywhere| {
+            everywhere
+              .name("everywhere")
+              .kind(CommandOptionType::Boolean)
+              .description("Forget your birthday entirely, in every server")
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("upcoming")
+          .kind(CommandOptionType::SubCommand)
+          .description("See the next birthdays in this server")
+          .create_sub_option(|count| {
+            count
+              .name("count")
+              .kind(CommandOptionType::Integer)
+              .description("How many birthdays to show (default 10)")
+              .min_int_value(1)
+              .max_int_value(MAX_UPCOMING)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("remind")
+          .kind(CommandOptionType::SubCommand)
+          .description("Get a DM a few days before someone's (or everyone's) birthday")
+          .create_sub_option(|user| {
+            user
+              .name("user")
+              .kind(CommandOptionType::User)
+              .description("Whose birthday to be reminded of (leave out for everyone here)")
+              .required(false)
+          })
+          .create_sub_option(|days| {
+            days
+              .name("days_before")
+              .kind(CommandOptionType::Integer)
+              .description("How many days ahead to remind you (default 3)")
+              .min_int_value(1)
+              .max_int_value(MAX_REMINDER_DAYS)
+              .required(false)
+          })
+          .create_sub_option(|cancel| {
+            cancel
+              .name("cancel")
+              .kind(CommandOptionType::Boolean)
+              .description("Stop this reminder instead")
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("export")
+          .kind(CommandOptionType::SubCommand)
+          .description("Get this server's birthdays as a calendar (.ics) file")
+      })
+      .create_option(|op| {
+        op.name("config")
+          .kind(CommandOptionType::SubCommand)
+          .description("(Admins) Set how birthdays in this server are announced")
+          .create_sub_option(|channel| {
+            channel
+              .name("channel")
+              .kind(CommandOptionType::Channel)
+              .channel_types(&[ChannelType::Text])
+              .description("The channel to announce birthdays in")
+              .required(false)
+          })
+          .create_sub_option(|role| {
+            role
+              .name("role")
+              .kind(CommandOptionType::Role)
+              .description("A role to give people for 24 hours on their birthday")
+              .required(false)
+          })
+          .create_sub_option(|remove| {
+            remove
+              .name("remove_role")
+              .kind(CommandOptionType::Boolean)
+              .description("Stop giving out a birthday role")
+              .required(false)
+          })
+          .create_sub_option(|message| {
+            message
+              .name("message")
+              .kind(CommandOptionType::String)
+              .description("Announcement text. Use {user}, {age} and {date}; \\n for new lines. 'default' to reset")
+              .required(false)
+          })
       })
   })
 }
@@ -40,98 +173,1345 @@ const DATE_OPTIONS: [&str; 8] = [
   "%_m/%d/%Y",  // 1/01/1999
 ];
 
+/// Each user's birthday. This is shared between servers; whether it is
+/// announced in a given server is decided by `guild_key`
 pub const BIRTHDAY_KEY: &str = "birthdays";
 pub const BIRTHDAY_FMT: &str = "%_m/%_d/%Y";
 
+/// Each user's timezone (an IANA name), used for when to announce their birthday
+pub const BIRTHDAY_TZ_KEY: &str = "birthdays:timezones";
+
+/// Where to announce birthdays for each server. Servers without a channel
+/// never have birthdays announced
+pub const BIRTHDAY_CHANNELS_KEY: &str = "birthdays:channels";
+
+/// The role to give people on their birthday, for each server
+pub const BIRTHDAY_ROLES_KEY: &str = "birthdays:roles";
+
+/// The announcement template for each server. See `render_template`
+pub const BIRTHDAY_TEMPLATES_KEY: &str = "birthdays:templates";
+
+const MAX_TEMPLATE_LENGTH: usize = 1000;
+
+pub const DEFAULT_TEMPLATE: &str = "Happy birthday {user}!\n{age} years";
+
+/// Users who don't want their age shown
+pub const BIRTHDAY_PRIVATE_KEY: &str = "birthdays:private";
+
+/// For users born on Feb 29, which day to celebrate on in other years
+pub const BIRTHDAY_LEAP_KEY: &str = "birthdays:leap_day";
+
+/// When each user's birthday was last announced in each server (unix timestamp)
+const BIRTHDAY_ANNOUNCED_KEY: &str = "birthdays:announced";
+
+/// Birthday reminders, as `{subscriber}:{guild}:{user}` (`user` is `*` for
+/// everyone in the server) to how many days ahead to send them
+const BIRTHDAY_REMINDERS_KEY: &str = "birthdays:reminders";
+
+/// Which birthday (the announcement timestamp) each `{subscriber}:{user}` was
+/// last reminded of. This is per user (not per reminder) so overlapping
+/// reminders don't send the same DM twice
+const BIRTHDAY_REMINDED_KEY: &str = "birthdays:reminded";
+
+const DEFAULT_REMINDER_DAYS: i64 = 3;
+const MAX_REMINDER_DAYS: i64 = 30;
+
+const DEFAULT_UPCOMING: usize = 10;
+const MAX_UPCOMING: usize = 25;
+
+/// How far back to look for missed birthdays, so a long outage doesn't end in
+/// a wall of belated messages
+const MAX_CATCH_UP_DAYS: i64 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeapDay {
+  Feb28,
+  Mar1,
+}
+
+impl LeapDay {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LeapDay::Feb28 => "feb28",
+      LeapDay::Mar1 => "mar1",
+    }
+  }
+
+  pub fn parse(value: &str) -> Option<LeapDay> {
+    match value {
+      "feb28" => Some(LeapDay::Feb28),
+      "mar1" => Some(LeapDay::Mar1),
+      _ => None,
+    }
+  }
+}
+
+/// Everything we know about someone's birthday
+#[derive(Clone, Debug)]
+pub struct Birthday {
+  pub month: u32,
+  pub day: u32,
+  pub year: Option<i32>,
+  pub hide_age: bool,
+  pub leap_day: LeapDay,
+  pub timezone: Tz,
+}
+
+impl Birthday {
+  /// Parses a date in `BIRTHDAY_KEY`. This is either `BIRTHDAY_FMT`, or the
+  /// same without the year
+  fn from_stored(stored: &str) -> Option<Birthday> {
+    let parts: Vec<&str> = stored.split('/').map(|part| part.trim()).collect();
+
+    let (month, day, year) = match parts[..] {
+      [month, day] => (month, day, None),
+      [month, day, year] => (month, day, Some(year.parse::<i32>().ok()?)),
+      _ => return None,
+    };
+
+    let month = month.parse::<u32>().ok()?;
+    let day = day.parse::<u32>().ok()?;
+
+    // 2000 was a leap year, so this also accepts Feb 29
+    NaiveDate::from_ymd_opt(year.unwrap_or(2000), month, day)?;
+
+    Some(Birthday {
+      month,
+      day,
+      year,
+      hide_age: false,
+      leap_day: LeapDay::Feb28,
+      timezone: EST5EDT,
+    })
+  }
+
+  /// The date to celebrate this birthday in `year`
+  pub fn date_in(&self, year: i32) -> Option<NaiveDate> {
+    match NaiveDate::from_ymd_opt(year, self.month, self.day) {
+      Some(date) => Some(date),
+      None if self.month == 2 && self.day == 29 => match self.leap_day {
+        LeapDay::Feb28 => NaiveDate::from_ymd_opt(year, 2, 28),
+        LeapDay::Mar1 => NaiveDate::from_ymd_opt(year, 3, 1),
+      },
+      None => None,
+    }
+  }
+
+  /// The next date (on or after `today`) to celebrate this birthday
+  pub fn next_date(&self, today: NaiveDate) -> Option<NaiveDate> {
+    [today.year(), today.year() + 1]
+      .iter()
+      .filter_map(|year| self.date_in(*year))
+      .find(|date| *date >= today)
+  }
+
+  /// How old this person turns in `year`, if they're ok with sharing it
+  pub fn age_in(&self, year: i32) -> Option<i32> {
+    if self.hide_age {
+      None
+    } else {
+      self.year.map(|born| year - born)
+    }
+  }
+}
+
+/// Parses a birthday given by a user, either with or without a year.
+/// Returns the string to store in `BIRTHDAY_KEY`
+fn parse_birthday_input(input: &str) -> Option<String> {
+  for option in DATE_OPTIONS {
+    if let Ok(result) = NaiveDate::parse_from_str(input, option) {
+      return Some(result.format(BIRTHDAY_FMT).to_string());
+    }
+  }
+
+  Birthday::from_stored(input)
+    .filter(|birthday| birthday.year.is_none())
+    .map(|birthday| format!("{:>2}/{:>2}", birthday.month, birthday.day))
+}
+
+/// Gets every birthday (and the settings that go with it), by user ID
+pub async fn load_birthdays(con: &mut Connection) -> RedisResult<HashMap<String, Birthday>> {
+  let (dates, timezones, private, leap_days): (
+    HashMap<String, String>,
+    HashMap<String, String>,
+    HashSet<String>,
+    HashMap<String, String>,
+  ) = pipe()
+    .hgetall(BIRTHDAY_KEY)
+    .hgetall(BIRTHDAY_TZ_KEY)
+    .smembers(BIRTHDAY_PRIVATE_KEY)
+    .hgetall(BIRTHDAY_LEAP_KEY)
+    .query_async(con)
+    .await?;
+
+  let mut birthdays = HashMap::new();
+
+  for (user_id, date) in dates {
+    if let Some(mut birthday) = Birthday::from_stored(&date) {
+      if let Some(timezone) = timezones
+        .get(&user_id)
+        .and_then(|zone| zone.parse::<Tz>().ok())
+      {
+        birthday.timezone = timezone;
+      }
+
+      if let Some(leap_day) = leap_days.get(&user_id).and_then(|day| LeapDay::parse(day)) {
+        birthday.leap_day = leap_day;
+      }
+
+      birthday.hide_age = private.contains(&user_id);
+      birthdays.insert(user_id, birthday);
+    }
+  }
+
+  Ok(birthdays)
+}
+
+/// Gets the birthdays of everyone who has opted in to announcements in a server
+pub async fn load_guild_birthdays(
+  con: &mut Connection,
+  guild_id: u64,
+) -> RedisResult<Vec<(u64, Birthday)>> {
+  let members: HashSet<String> = con.smembers(guild_key(guild_id)).await?;
+
+  let birthdays = load_birthdays(con).await?;
+
+  Ok(
+    birthdays
+      .into_iter()
+      .filter(|(user_id, _)| members.contains(user_id))
+      .filter_map(|(user_id, birthday)| {
+        user_id
+          .parse::<u64>()
+          .ok()
+          .map(|user_id| (user_id, birthday))
+      })
+      .collect(),
+  )
+}
+
+/// The set of users who have opted in to having their birthday announced in a server
+#[inline]
+pub fn guild_key(guild_id: u64) -> String {
+  format!("birthdays:guild:{}", guild_id)
+}
+
+/// Matches every server's `guild_key`
+const GUILD_KEY_PATTERN: &str = "birthdays:guild:*";
+
 pub async fn interaction_birthday(
   ctx: &Context,
   interaction: &ApplicationCommandInteraction,
 ) -> Result<(), String> {
   let options = &interaction.data.options;
 
-  if options.len() < 1 {
-    return Err(String::from("Must provide a birthday"));
+  if options.is_empty() {
+    return Err(String::from("Must have subcommand"));
   } else if !interaction.guild_id.is_some() {
     return Err(String::from("Must be in a server"));
   }
 
+  match options[0].name.as_str() {
+    "set" => set_birthday(ctx, interaction).await,
+    "remove" => remove_birthday(ctx, interaction).await,
+    "upcoming" => upcoming_birthdays(ctx, interaction).await,
+    "remind" => remind_birthday(ctx, interaction).await,
+    "export" => export_birthdays(ctx, interaction).await,
+    "config" => config_birthday(ctx, interaction).await,
+    _ => Err(String::from("Unexpected command")),
+  }
+}
+
+async fn set_birthday(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options[0].options;
+
+  if options.is_empty() {
+    return Err(String::from("Must provide a birthday"));
+  }
+
   let user_id = interaction.user.id.0;
+  let guild_id = interaction.guild_id.unwrap().0;
 
-  let date_str = get_str_or_error(&options[0].value, "You must provide a birthday")?;
+  let mut date_str: Option<String> = None;
+  let mut hide_age: Option<bool> = None;
+  let mut leap_day: Option<LeapDay> = None;
 
-  if date_str == "None" {
-    {
-      let lock = {
-        let mut context = ctx.data.write().await;
-        context
-          .get_mut::<RedisConnectionKey>()
-          .expect("Expected redis connection")
-          .clone()
-      };
+  for option in options {
+    match option.name.as_str() {
+      "date" => {
+        date_str = Some(get_str_or_error(
+          &option.value,
+          "You must provide a birthday",
+        )?)
+      }
+      "hide_age" => hide_age = option.value.as_ref().and_then(|value| value.as_bool()),
+      "leap_day" => {
+        leap_day = option
+          .value
+          .as_ref()
+          .and_then(|value| value.as_str())
+          .and_then(LeapDay::parse)
+      }
+      _ => {}
+    }
+  }
 
-      let mut redis_client = lock.lock().await;
-      let result: Result<(), RedisError> = redis_client.0.hdel(BIRTHDAY_KEY, user_id).await;
+  let date_str = match date_str {
+    Some(date) => date,
+    None => return Err(String::from("You must provide a birthday")),
+  };
+
+  let birthday = match parse_birthday_input(date_str.trim()) {
+    Some(birthday) => birthday,
+    None => return Err(format!("Birthday '{}' is not a valid format", date_str)),
+  };
+
+  let channel: Option<u64> = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut pipeline = pipe();
+    pipeline
+      .atomic()
+      .hset(BIRTHDAY_KEY, user_id, &birthday)
+      .ignore()
+      .sadd(guild_key(guild_id), user_id)
+      .ignore();
 
-      if let Err(error) = result {
-        return Err(error.to_string());
+    match hide_age {
+      Some(true) => {
+        pipeline.sadd(BIRTHDAY_PRIVATE_KEY, user_id).ignore();
       }
+      Some(false) => {
+        pipeline.srem(BIRTHDAY_PRIVATE_KEY, user_id).ignore();
+      }
+      None => {}
     }
 
-    let _ = interaction
-      .create_interaction_response(ctx, |f| {
-        f.kind(InteractionResponseType::ChannelMessageWithSource)
-          .interaction_response_data(|msg| msg.content("Removed your birthday").ephemeral(true))
-      })
+    if let Some(leap_day) = leap_day {
+      pipeline
+        .hset(BIRTHDAY_LEAP_KEY, user_id, leap_day.as_str())
+        .ignore();
+    }
+
+    let mut redis_client = lock.lock().await;
+    let result: RedisResult<(Option<u64>,)> = pipeline
+      .hget(BIRTHDAY_CHANNELS_KEY, guild_id)
+      .query_async(&mut redis_client.0)
       .await;
+
+    match result {
+      Ok((channel,)) => channel,
+      Err(error) => return Err(error.to_string()),
+    }
+  };
+
+  let mut message = match channel {
+    Some(channel) => format!(
+      "Set your birthday to {}. It will be announced in <#{}>",
+      birthday.trim(), channel
+    ),
+    None => format!(
+      "Set your birthday to {}. This server has no birthday channel yet, so it won't be announced until an admin sets one up",
+      birthday.trim()
+    ),
+  };
+
+  if leap_day.is_none() && birthday.replace(' ', "").starts_with("2/29") {
+    message +=
+      "\nIn years without a Feb 29, it will be celebrated on Feb 28. Set `leap_day` to change this";
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+async fn remove_birthday(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options[0].options;
+
+  let everywhere = match options.get(0).and_then(|op| op.value.as_ref()) {
+    Some(field) => match field.as_bool() {
+      Some(boolean) => boolean,
+      None => return Err(String::from("This must be a true or false")),
+    },
+    None => false,
+  };
+
+  let user_id = interaction.user.id.0;
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let result: RedisResult<()> = {
+    let mut redis_client = lock.lock().await;
+
+    if everywhere {
+      forget_user(&mut redis_client.0, user_id).await
+    } else {
+      redis_client.0.srem(guild_key(guild_id), user_id).await
+    }
+  };
+
+  if let Err(error) = result {
+    return Err(error.to_string());
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content(if everywhere {
+              "Removed your birthday"
+            } else {
+              "Your birthday will no longer be announced in this server"
+            })
+            .ephemeral(true)
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Removes everything stored about a user's birthday, in every server,
+/// along with their reminders and everyone's reminders about them
+async fn forget_user(con: &mut Connection, user_id: u64) -> RedisResult<()> {
+  // People can opt in to servers that haven't set up a birthday channel, so
+  // every server's set is checked, not only the ones with a channel
+  let guilds: Vec<String> = {
+    let mut keys = con.scan_match(GUILD_KEY_PATTERN).await?;
+    let mut guilds = vec![];
+
+    while let Some(key) = keys.next_item().await {
+      guilds.push(key);
+    }
+
+    guilds
+  };
+
+  let (reminders, reminded, announced): (Vec<String>, Vec<String>, Vec<String>) = pipe()
+    .hkeys(BIRTHDAY_REMINDERS_KEY)
+    .hkeys(BIRTHDAY_REMINDED_KEY)
+    .hkeys(BIRTHDAY_ANNOUNCED_KEY)
+    .query_async(con)
+    .await?;
+
+  let user = user_id.to_string();
+
+  let mut pipeline = pipe();
+  pipeline
+    .atomic()
+    .hdel(BIRTHDAY_KEY, user_id)
+    .ignore()
+    .hdel(BIRTHDAY_TZ_KEY, user_id)
+    .ignore()
+    .srem(BIRTHDAY_PRIVATE_KEY, user_id)
+    .ignore()
+    .hdel(BIRTHDAY_LEAP_KEY, user_id)
+    .ignore();
+
+  for key in guilds {
+    pipeline.srem(key, user_id).ignore();
+  }
+
+  // `{subscriber}:{guild}:{user}`
+  for field in reminders {
+    let mut parts = field.split(':');
+
+    if parts.next() == Some(user.as_str()) || parts.nth(1) == Some(user.as_str()) {
+      pipeline.hdel(BIRTHDAY_REMINDERS_KEY, &field).ignore();
+    }
+  }
+
+  // `{subscriber}:{user}`
+  for field in reminded {
+    if field.split(':').any(|part| part == user) {
+      pipeline.hdel(BIRTHDAY_REMINDED_KEY, &field).ignore();
+    }
+  }
+
+  // `{guild}:{user}`
+  for field in announced {
+    if field.split(':').nth(1) == Some(user.as_str()) {
+      pipeline.hdel(BIRTHDAY_ANNOUNCED_KEY, &field).ignore();
+    }
+  }
+
+  pipeline.query_async(con).await
+}
+
+async fn upcoming_birthdays(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options[0].options;
+
+  let count = match options.get(0).and_then(|op| op.value.as_ref()) {
+    Some(field) => match field.as_u64() {
+      Some(count) => (count as usize).min(MAX_UPCOMING),
+      None => return Err(String::from("Count must be a number")),
+    },
+    None => DEFAULT_UPCOMING,
+  };
+
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let birthdays = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    load_guild_birthdays(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Error getting birthdays: {}", err))?
+  };
+
+  let today = Utc::now().with_timezone(&EST5EDT).date_naive();
+
+  let mut upcoming: Vec<(NaiveDate, u64, Birthday)> = birthdays
+    .into_iter()
+    .filter_map(|(user_id, birthday)| {
+      birthday
+        .next_date(today)
+        .map(|date| (date, user_id, birthday))
+    })
+    .collect();
+
+  upcoming.sort_by_key(|(date, user_id, _)| (*date, *user_id));
+  upcoming.truncate(count);
+
+  let description = if upcoming.is_empty() {
+    String::from("Nobody in this server has set their birthday yet. Use `/birthday set`!")
   } else {
-    let mut result: Option<NaiveDate> = None;
+    upcoming
+      .iter()
+      .map(|(date, user_id, birthday)| {
+        let days = date.signed_duration_since(today).num_days();
 
-    for option in DATE_OPTIONS {
-      result = match NaiveDate::parse_from_str(&date_str, option) {
-        Ok(result) => Some(result),
-        Err(_) => None,
-      };
+        let mut line = format!(
+          "**{}** <@{}> ({})",
+          date.format("%b %-d"),
+          user_id,
+          match days {
+            0 => String::from("today"),
+            1 => String::from("tomorrow"),
+            _ => format!("in {} days", days),
+          }
+        );
+
+        if let Some(age) = birthday.age_in(date.year()) {
+          line += &format!(", turning {}", age);
+        }
+
+        line
+      })
+      .collect::<Vec<String>>()
+      .join("\n")
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg.embed(|e| {
+            e.color(Color::BLITZ_BLUE)
+              .title("Upcoming birthdays")
+              .description(description)
+          })
+        })
+    })
+    .await;
 
-      if result.is_some() {
-        break;
+  Ok(())
+}
+
+async fn remind_birthday(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let mut target: Option<u64> = None;
+  let mut days_before = DEFAULT_REMINDER_DAYS;
+  let mut cancel = false;
+
+  for option in &interaction.data.options[0].options {
+    let value = match &option.value {
+      Some(value) => value,
+      None => continue,
+    };
+
+    match option.name.as_str() {
+      "user" => target = value.as_str().and_then(|id| id.parse::<u64>().ok()),
+      "days_before" => match value.as_i64() {
+        Some(days) => days_before = days.clamp(1, MAX_REMINDER_DAYS),
+        None => return Err(String::from("Days before must be a number")),
+      },
+      "cancel" => cancel = value.as_bool().unwrap_or(false),
+      _ => {}
+    }
+  }
+
+  let guild_id = interaction.guild_id.unwrap().0;
+  let field = format!(
+    "{}:{}:{}",
+    interaction.user.id.0,
+    guild_id,
+    target.map_or(String::from("*"), |id| id.to_string())
+  );
+
+  let who = match target {
+    Some(id) => format!("<@{}>'s birthday", id),
+    None => String::from("everyone's birthdays in this server"),
+  };
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let message = if cancel {
+    let result: RedisResult<()> = {
+      let mut redis_client = lock.lock().await;
+      redis_client.0.hdel(BIRTHDAY_REMINDERS_KEY, &field).await
+    };
+
+    if let Err(error) = result {
+      return Err(error.to_string());
+    }
+
+    format!("You will no longer be reminded of {}", who)
+  } else {
+    let mut redis_client = lock.lock().await;
+
+    if let Some(id) = target {
+      let shared: bool = redis_client
+        .0
+        .sismember(guild_key(guild_id), id)
+        .await
+        .map_err(|err| err.to_string())?;
+
+      if !shared {
+        return Err(String::from(
+          "That person hasn't shared their birthday in this server",
+        ));
       }
     }
 
-    if result.is_none() {
-      return Err(format!("Birthday '{}' is not a valid format", date_str));
+    let result: RedisResult<()> = redis_client
+      .0
+      .hset(BIRTHDAY_REMINDERS_KEY, &field, days_before)
+      .await;
+
+    if let Err(error) = result {
+      return Err(error.to_string());
     }
 
-    let birthday = result.unwrap().format(BIRTHDAY_FMT).to_string();
+    format!(
+      "You will get a DM {} {} before {}. Make sure you allow DMs from this server!",
+      days_before,
+      if days_before == 1 { "day" } else { "days" },
+      who
+    )
+  };
 
-    {
-      let lock = {
-        let mut context = ctx.data.write().await;
-        context
-          .get_mut::<RedisConnectionKey>()
-          .expect("Expected redis connection")
-          .clone()
-      };
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
 
-      let mut redis_client = lock.lock().await;
-      let result: Result<(), RedisError> =
-        redis_client.0.hset(BIRTHDAY_KEY, user_id, &birthday).await;
+  Ok(())
+}
 
-      if let Err(error) = result {
-        return Err(error.to_string());
+async fn export_birthdays(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let guild_id = interaction.guild_id.unwrap();
+
+  let birthdays = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    load_guild_birthdays(&mut redis_client.0, guild_id.0)
+      .await
+      .map_err(|err| format!("Error getting birthdays: {}", err))?
+  };
+
+  if birthdays.is_empty() {
+    return Err(String::from(
+      "Nobody in this server has set their birthday yet",
+    ));
+  }
+
+  let today = Utc::now().with_timezone(&EST5EDT).date_naive();
+  let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+  let mut calendar = String::from(concat!(
+    "BEGIN:VCALENDAR\r\n",
+    "VERSION:2.0\r\n",
+    "PRODID:-//Safety-chan//Birthdays//EN\r\n",
+    "CALSCALE:GREGORIAN\r\n",
+  ));
+
+  for (user_id, birthday) in birthdays {
+    // Don't use the birth year, so ages aren't leaked
+    let start = match birthday.date_in(today.year()) {
+      Some(date) => date,
+      None => continue,
+    };
+
+    let name = match ctx.cache.member(guild_id, user_id) {
+      Some(member) => member.display_name().to_string(),
+      None => match UserId(user_id).to_user(ctx).await {
+        Ok(user) => user.name,
+        Err(_) => format!("User {}", user_id),
+      },
+    };
+
+    // Leap day birthdays have to follow the person's choice in other years.
+    // The 60th day of the year is Feb 29 in leap years, and Mar 1 otherwise
+    let rule = if birthday.month == 2 && birthday.day == 29 {
+      match birthday.leap_day {
+        LeapDay::Feb28 => "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1",
+        LeapDay::Mar1 => "FREQ=YEARLY;BYYEARDAY=60",
       }
+    } else {
+      "FREQ=YEARLY"
     };
 
-    let _ = interaction
-      .create_interaction_response(ctx, |f| {
-        f.kind(InteractionResponseType::ChannelMessageWithSource)
-          .interaction_response_data(|msg| {
-            msg
-              .content(format!("Set your birthday to {}", birthday))
-              .ephemeral(true)
+    calendar += &format!(
+      concat!(
+        "BEGIN:VEVENT\r\n",
+        "UID:{}-{}@safety-rust\r\n",
+        "DTSTAMP:{}\r\n",
+        "DTSTART;VALUE=DATE:{}\r\n",
+        "RRULE:{}\r\n",
+        "SUMMARY:{}\r\n",
+        "TRANSP:TRANSPARENT\r\n",
+        "END:VEVENT\r\n",
+      ),
+      user_id,
+      guild_id.0,
+      stamp,
+      start.format("%Y%m%d"),
+      rule,
+      escape_ical(&format!("{}'s birthday", name))
+    );
+  }
+
+  calendar += "END:VCALENDAR\r\n";
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content("Here are this server's birthdays. Import this file into your calendar app")
+            .add_file((calendar.as_bytes(), "birthdays.ics"))
+            .ephemeral(true)
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Escapes text for use in an iCalendar (RFC 5545) property value
+fn escape_ical(text: &str) -> String {
+  text
+    .replace('\\', "\\\\")
+    .replace(';', "\\;")
+    .replace(',', "\\,")
+    .replace('\n', "\\n")
+}
+
+async fn config_birthday(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let is_admin = interaction
+    .member
+    .as_ref()
+    .and_then(|member| member.permissions)
+    .map_or(false, |permissions| permissions.manage_guild());
+
+  if !is_admin {
+    return Err(String::from(
+      "You need the Manage Server permission to do this",
+    ));
+  }
+
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let mut pipeline = pipe();
+  pipeline.atomic();
+
+  let mut changes: Vec<String> = vec![];
+
+  for option in &interaction.data.options[0].options {
+    let value = match &option.value {
+      Some(value) => value,
+      None => continue,
+    };
+
+    match option.name.as_str() {
+      "channel" | "role" => {
+        let id = match value.as_str().and_then(|id| id.parse::<u64>().ok()) {
+          Some(id) => id,
+          None => return Err(format!("'{}' is not a valid {}", value, option.name)),
+        };
+
+        if option.name == "channel" {
+          pipeline.hset(BIRTHDAY_CHANNELS_KEY, guild_id, id).ignore();
+          changes.push(format!("Birthdays will be announced in <#{}>", id));
+        } else {
+          pipeline.hset(BIRTHDAY_ROLES_KEY, guild_id, id).ignore();
+          changes.push(format!("People will get <@&{}> on their birthday", id));
+        }
+      }
+      "remove_role" => {
+        if value.as_bool() == Some(true) {
+          pipeline.hdel(BIRTHDAY_ROLES_KEY, guild_id).ignore();
+          changes.push(String::from("Nobody will get a birthday role"));
+        }
+      }
+      "message" => {
+        let template = value.as_str().unwrap_or_default().replace("\\n", "\n");
+
+        if template.len() > MAX_TEMPLATE_LENGTH {
+          return Err(format!(
+            "Messages can be at most {} characters",
+            MAX_TEMPLATE_LENGTH
+          ));
+        } else if template.trim() == "default" {
+          pipeline.hdel(BIRTHDAY_TEMPLATES_KEY, guild_id).ignore();
+          changes.push(String::from("Birthdays will use the default message"));
+        } else {
+          changes.push(format!(
+            "Birthdays will be announced like this:\n>>> {}",
+            render_template(&template, "@someone", Some(30), "January 1")
+          ));
+          pipeline
+            .hset(BIRTHDAY_TEMPLATES_KEY, guild_id, template)
+            .ignore();
+        }
+      }
+      _ => {}
+    }
+  }
+
+  if changes.is_empty() {
+    return Err(String::from("You must provide something to change"));
+  }
+
+  {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    let result: RedisResult<()> = pipeline.query_async(&mut redis_client.0).await;
+
+    if let Err(error) = result {
+      return Err(error.to_string());
+    }
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(changes.join("\n")).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Fills in an announcement template. `{user}` is the person, `{age}` how old
+/// they are turning and `{date}` their birthday.
+/// Lines with `{age}` are left out when the age is hidden or unknown
+pub fn render_template(template: &str, user: &str, age: Option<i32>, date: &str) -> String {
+  template
+    .lines()
+    .filter_map(|line| match age {
+      Some(age) => Some(line.replace("{age}", &age.to_string())),
+      None if line.contains("{age}") => None,
+      None => Some(line.to_string()),
+    })
+    .map(|line| line.replace("{user}", user).replace("{date}", date))
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+/// Birthdays used to be global and announced in a single channel.
+/// If `guild_id` has no birthday channel yet, this makes `channel_id` its
+/// channel and opts everyone with a birthday into that server
+pub async fn migrate_birthdays(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  guild_id: u64,
+  channel_id: u64,
+) -> RedisResult<()> {
+  let mut redis_client = connection.lock().await;
+
+  let added: bool = redis_client
+    .0
+    .hset_nx(BIRTHDAY_CHANNELS_KEY, guild_id, channel_id)
+    .await?;
+
+  if added {
+    let users: Vec<u64> = redis_client.0.hkeys(BIRTHDAY_KEY).await?;
+
+    if !users.is_empty() {
+      let _: () = redis_client.0.sadd(guild_key(guild_id), users).await?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Announces every birthday whose (local) announcement time has passed since
+/// it was last announced, in every server with a birthday channel.
+/// Each person is announced at `announce_hour` in their own timezone (or
+/// Eastern if they have not set one). Birthdays missed by less than
+/// `MAX_CATCH_UP_DAYS` are announced as belated.
+/// Each announcement is claimed in Redis first, so nobody is announced twice.
+/// This stops as soon as `lease` is lost, leaving the rest to the new leader
+pub async fn catch_up_birthdays(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  scheduler: &Arc<Mutex<Scheduler>>,
+  http: &Arc<Http>,
+  lease: &Lease,
+  announce_hour: u32,
+  now: DateTime<Utc>,
+) -> Result<(), String> {
+  let (birthdays, announced, channels, roles, templates) = {
+    let mut redis_client = connection.lock().await;
+    let birthdays = load_birthdays(&mut redis_client.0)
+      .await
+      .map_err(|err| format!("Error getting birthdays: {}", err))?;
+
+    let (announced, channels, roles, templates): (
+      HashMap<String, i64>,
+      HashMap<u64, u64>,
+      HashMap<u64, u64>,
+      HashMap<u64, String>,
+    ) = pipe()
+      .hgetall(BIRTHDAY_ANNOUNCED_KEY)
+      .hgetall(BIRTHDAY_CHANNELS_KEY)
+      .hgetall(BIRTHDAY_ROLES_KEY)
+      .hgetall(BIRTHDAY_TEMPLATES_KEY)
+      .query_async(&mut redis_client.0)
+      .await
+      .map_err(|err| format!("Error getting birthdays: {}", err))?;
+
+    (birthdays, announced, channels, roles, templates)
+  };
+
+  for (guild_id, channel_id) in channels {
+    let members: Vec<String> = {
+      let mut redis_client = connection.lock().await;
+      redis_client
+        .0
+        .smembers(guild_key(guild_id))
+        .await
+        .map_err(|err| format!("Error getting birthdays for {}: {}", guild_id, err))?
+    };
+
+    for user_id in members {
+      let birthday = match birthdays.get(&user_id) {
+        Some(birthday) => birthday,
+        None => continue,
+      };
+
+      let occurrence = match last_occurrence(birthday, announce_hour, now) {
+        Some(occurrence) => occurrence,
+        None => continue,
+      };
+
+      let field = format!("{}:{}", guild_id, user_id);
+      let last_announced = announced.get(&field).copied();
+
+      if last_announced.map_or(false, |last| last >= occurrence.timestamp()) {
+        continue;
+      }
+
+      // Never announced before (e.g. the birthday was just set): don't backfill
+      let max_lateness = if last_announced.is_some() {
+        Duration::days(MAX_CATCH_UP_DAYS)
+      } else {
+        Duration::days(1)
+      };
+
+      if now.signed_duration_since(occurrence) > max_lateness {
+        continue;
+      }
+
+      let claimed = {
+        let mut redis_client = connection.lock().await;
+        advance_field_checkpoint(
+          &mut redis_client.0,
+          BIRTHDAY_ANNOUNCED_KEY,
+          &field,
+          occurrence.timestamp(),
+        )
+        .await
+        .map_err(|err| format!("Could not save birthday announcement: {}", err))?
+      };
+
+      if !claimed {
+        continue;
+      }
+
+      let local_today = now.with_timezone(&birthday.timezone).date_naive();
+      let belated = local_today != occurrence.date_naive();
+      let date = occurrence.format("%B %-d").to_string();
+
+      let description = render_template(
+        templates
+          .get(&guild_id)
+          .map(|template| template.as_str())
+          .unwrap_or(DEFAULT_TEMPLATE),
+        &format!("<@{}>", user_id),
+        birthday.age_in(occurrence.year()),
+        &date,
+      );
+
+      if !lease.is_leader() {
+        release_announcement(connection, &field, occurrence.timestamp(), last_announced).await;
+        return Ok(());
+      }
+
+      let sent = ChannelId(channel_id)
+        .send_message(http, |m| {
+          m.content(format!("<@{}>", user_id)).embed(|e| {
+            e.color(Color::BLITZ_BLUE)
+              .title(if belated {
+                "Happy belated birthday!"
+              } else {
+                "Happy birthday!"
+              })
+              .description(description);
+
+            if belated {
+              e.footer(|f| f.text(format!("Their birthday was on {}", date)));
+            }
+
+            e
           })
+        })
+        .await;
+
+      if let Err(error) = sent {
+        println!(
+          "Could not announce {}'s birthday in {}: {}",
+          user_id, guild_id, error
+        );
+
+        release_announcement(connection, &field, occurrence.timestamp(), last_announced).await;
+        continue;
+      }
+
+      let role_until = occurrence + Duration::days(1);
+
+      if let (Some(role), true) = (roles.get(&guild_id), role_until > now) {
+        give_birthday_role(
+          scheduler,
+          http,
+          guild_id,
+          &user_id,
+          *role,
+          role_until.timestamp(),
+        )
+        .await;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Lets go of the claim on an announcement that didn't go out, so it's tried
+/// again (until it's too late)
+async fn release_announcement(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  field: &str,
+  occurrence: i64,
+  last_announced: Option<i64>,
+) {
+  let mut redis_client = connection.lock().await;
+
+  if let Err(error) = release_field_checkpoint(
+    &mut redis_client.0,
+    BIRTHDAY_ANNOUNCED_KEY,
+    field,
+    occurrence,
+    last_announced,
+  )
+  .await
+  {
+    println!("Could not let go of birthday announcement: {}", error);
+  }
+}
+
+/// DMs everyone whose birthday reminders are due. A reminder is due
+/// `days_before` days before the birthday is announced, and is sent once per birthday
+pub async fn send_birthday_reminders(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  http: &Arc<Http>,
+  announce_hour: u32,
+  now: DateTime<Utc>,
+) -> Result<(), String> {
+  let (birthdays, reminders, reminded) = {
+    let mut redis_client = connection.lock().await;
+
+    let (reminders, reminded): (HashMap<String, i64>, HashMap<String, i64>) = pipe()
+      .hgetall(BIRTHDAY_REMINDERS_KEY)
+      .hgetall(BIRTHDAY_REMINDED_KEY)
+      .query_async(&mut redis_client.0)
+      .await
+      .map_err(|err| format!("Error getting birthday reminders: {}", err))?;
+
+    if reminders.is_empty() {
+      return Ok(());
+    }
+
+    let birthdays = load_birthdays(&mut redis_client.0)
+      .await
+      .map_err(|err| format!("Error getting birthdays: {}", err))?;
+
+    (birthdays, reminders, reminded)
+  };
+
+  let mut guild_members: HashMap<u64, HashSet<String>> = HashMap::new();
+
+  for (field, days_before) in reminders {
+    let (subscriber, guild_id, target) = match field.split(':').collect::<Vec<&str>>()[..] {
+      [subscriber, guild_id, target] => {
+        match (subscriber.parse::<u64>(), guild_id.parse::<u64>()) {
+          (Ok(subscriber), Ok(guild_id)) => (subscriber, guild_id, target.to_string()),
+          _ => continue,
+        }
+      }
+      _ => continue,
+    };
+
+    if !guild_members.contains_key(&guild_id) {
+      let members: HashSet<String> = {
+        let mut redis_client = connection.lock().await;
+        redis_client
+          .0
+          .smembers(guild_key(guild_id))
+          .await
+          .map_err(|err| format!("Error getting birthdays for {}: {}", guild_id, err))?
+      };
+
+      guild_members.insert(guild_id, members);
+    }
+
+    let subscriber_str = subscriber.to_string();
+    let targets: Vec<&String> = guild_members[&guild_id]
+      .iter()
+      .filter(|user_id| {
+        if target == "*" {
+          **user_id != subscriber_str
+        } else {
+          **user_id == target
+        }
       })
-      .await;
+      .collect();
+
+    for user_id in targets {
+      let birthday = match birthdays.get(user_id) {
+        Some(birthday) => birthday,
+        None => continue,
+      };
+
+      let occurrence = match next_occurrence(birthday, announce_hour, now) {
+        Some(occurrence) => occurrence,
+        None => continue,
+      };
+
+      if now < occurrence - Duration::days(days_before) {
+        continue;
+      }
+
+      let reminded_field = format!("{}:{}", subscriber, user_id);
+
+      if reminded
+        .get(&reminded_field)
+        .map_or(false, |last| *last >= occurrence.timestamp())
+      {
+        continue;
+      }
+
+      let claimed = {
+        let mut redis_client = connection.lock().await;
+        advance_field_checkpoint(
+          &mut redis_client.0,
+          BIRTHDAY_REMINDED_KEY,
+          &reminded_field,
+          occurrence.timestamp(),
+        )
+        .await
+        .map_err(|err| format!("Could not save birthday reminder: {}", err))?
+      };
+
+      if !claimed {
+        continue;
+      }
+
+      let local_today = now.with_timezone(&birthday.timezone).date_naive();
+      let days = occurrence
+        .date_naive()
+        .signed_duration_since(local_today)
+        .num_days();
+
+      let mut message = format!(
+        "Heads up! <@{}>'s birthday is {}, on {}",
+        user_id,
+        match days {
+          0 => String::from("today"),
+          1 => String::from("tomorrow"),
+          _ => format!("in {} days", days),
+        },
+        occurrence.format("%B %-d")
+      );
+
+      if let Some(age) = birthday.age_in(occurrence.year()) {
+        message += &format!(" (turning {})", age);
+      }
+
+      match UserId(subscriber).create_dm_channel(http).await {
+        Ok(channel) => {
+          let _ = channel.say(http, message).await;
+        }
+        Err(error) => println!(
+          "Could not DM {} a birthday reminder: {:?}",
+          subscriber, error
+        ),
+      }
+    }
   }
 
   Ok(())
 }
+
+/// Gives someone the birthday role, and schedules taking it away at `until`
+async fn give_birthday_role(
+  scheduler: &Arc<Mutex<Scheduler>>,
+  http: &Arc<Http>,
+  guild_id: u64,
+  user_id: &str,
+  role: u64,
+  until: i64,
+) {
+  let user_id = match user_id.parse::<u64>() {
+    Ok(id) => id,
+    Err(_) => return,
+  };
+
+  if let Err(error) = http
+    .add_member_role(guild_id, user_id, role, Some("Birthday"))
+    .await
+  {
+    println!(
+      "Could not give birthday role {} to {} in {}: {:?}",
+      role, user_id, guild_id, error
+    );
+    return;
+  }
+
+  let removal = RoleRemoval {
+    guild: guild_id,
+    user: user_id,
+    role,
+  };
+
+  let mut redis_scheduler = scheduler.lock().await;
+  if let Err(error) = redis_scheduler.schedule_role_removal(&removal, until).await {
+    println!("Could not schedule birthday role removal: {:?}", error);
+  }
+}
+
+/// When `birthday` should be announced in `year`, at `hour` local time in the
+/// person's timezone
+fn occurrence_in(birthday: &Birthday, year: i32, hour: u32) -> Option<DateTime<Tz>> {
+  let time = birthday
+    .date_in(year)
+    .and_then(|date| date.and_hms_opt(hour, 0, 0))?;
+
+  // If the clocks skip over that hour, announce it an hour later
+  birthday
+    .timezone
+    .from_local_datetime(&time)
+    .earliest()
+    .or_else(|| {
+      birthday
+        .timezone
+        .from_local_datetime(&(time + Duration::hours(1)))
+        .earliest()
+    })
+}
+
+/// Finds the most recent time at or before `now` that `birthday` should be announced
+fn last_occurrence(birthday: &Birthday, hour: u32, now: DateTime<Utc>) -> Option<DateTime<Tz>> {
+  let year = now.with_timezone(&birthday.timezone).year();
+
+  [year, year - 1]
+    .iter()
+    .filter_map(|year| occurrence_in(birthday, *year, hour))
+    .find(|occurrence| *occurrence <= now)
+}
+
+/// Finds the first time after `now` that `birthday` should be announced
+fn next_occurrence(birthday: &Birthday, hour: u32, now: DateTime<Utc>) -> Option<DateTime<Tz>> {
+  let year = now.with_timezone(&birthday.timezone).year();
+
+  [year, year + 1]
+    .iter()
+    .filter_map(|year| occurrence_in(birthday, *year, hour))
+    .find(|occurrence| *occurrence > now)
+}
@@ -1,17 +1,167 @@
-use redis::{cmd, AsyncCommands};
+use redis::{aio::Connection, cmd, pipe, AsyncCommands};
 use serenity::{
   builder::CreateApplicationCommands,
-  model::application::{
-    command::*,
-    interaction::{application_command::*, *},
+  model::{
+    application::{
+      command::*,
+      interaction::{
+        application_command::*, autocomplete::AutocompleteInteraction,
+        message_component::MessageComponentInteraction, modal::ModalSubmitInteraction, *,
+      },
+    },
+    prelude::{
+      component::{ActionRowComponent, InputTextStyle},
+      UserId,
+    },
   },
   prelude::*,
 };
+use sha2::{Digest, Sha256};
 
 use crate::util::scheduler::RedisConnectionKey;
 
+pub const COPY_SLOT_PREFIX: &str = "copy_slot:";
+pub const COPY_PICK_PREFIX: &str = "copy_pick:";
+
+/// How many copies are remembered for each member, newest first
+const MAX_COPY_HISTORY: isize = 25;
+
+/// Slot names show up in autocomplete, which can list at most 25 choices
+const MAX_COPY_SLOTS: usize = 25;
+const MAX_SLOT_NAME_LENGTH: u64 = 32;
+
+/// How long a message waits for its slot to be picked in `copy to slot`
+const PENDING_COPY_SECONDS: usize = 15 * 60;
+
+/// Messages can be at most 2000 characters. Copies are cut down to this, to
+/// leave room for who they're from when they're pasted
+const MAX_COPY_LENGTH: usize = 1900;
+
+/// How much of a copy is shown when it's set, so both the new and prior copy
+/// fit in one message
+const MAX_SHOWN_LENGTH: usize = 900;
+
+/// Select menu option labels can be at most 100 characters
+const MAX_PREVIEW_LENGTH: usize = 100;
+
 pub fn copy_command(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
-  commands.create_application_command(|command| command.name("copy").kind(CommandType::Message))
+  commands
+    .create_application_command(|command| command.name("copy").kind(CommandType::Message))
+    .create_application_command(|command| command.name("copy to slot").kind(CommandType::Message))
+}
+
+fn copy_key(user_id: u64, guild_id: u64) -> String {
+  format!("{}:{}:copy", user_id, guild_id)
+}
+
+fn slots_key(user_id: u64, guild_id: u64) -> String {
+  format!("{}:{}:copy:slots", user_id, guild_id)
+}
+
+fn history_key(user_id: u64, guild_id: u64) -> String {
+  format!("{}:{}:copy:history", user_id, guild_id)
+}
+
+/// A message from `copy to slot`, waiting for its slot name, as its author and
+/// contents
+fn pending_key(interaction_id: u64) -> String {
+  format!("copy:pending:{}", interaction_id)
+}
+
+fn slot_name(name: &str) -> String {
+  name.trim().to_lowercase()
+}
+
+/// Cuts `text` down to at most `max` characters
+fn truncate(text: &str, max: usize) -> String {
+  if text.chars().count() > max {
+    format!("{}…", text.chars().take(max - 1).collect::<String>())
+  } else {
+    String::from(text)
+  }
+}
+
+fn quote(contents: &str) -> String {
+  truncate(&contents.replace("\n", "\n> "), MAX_SHOWN_LENGTH)
+}
+
+/// What's posted for a paste. Copies from before they were cut down are cut
+/// down here instead
+fn paste_message(name: &str, copy: &str) -> String {
+  format!(
+    "Paste for {}: \n>>> {}",
+    name,
+    truncate(copy, MAX_COPY_LENGTH)
+  )
+}
+
+/// Saves a copy of `user_id`'s message, to a named slot or their main copy,
+/// and adds it to their history. Returns what was there before
+async fn save_copy(
+  conn: &mut Connection,
+  user_id: u64,
+  guild_id: u64,
+  slot: Option<&str>,
+  contents: &str,
+) -> Result<Option<String>, String> {
+  let contents = &truncate(contents, MAX_COPY_LENGTH);
+
+  let old_value: Option<String> = match slot {
+    Some(slot) => {
+      let key = slots_key(user_id, guild_id);
+
+      let old_value: Option<String> = conn
+        .hget(&key, slot)
+        .await
+        .map_err(|err| format!("Error saving message: {}", err))?;
+
+      if old_value.is_none() {
+        let slots: usize = conn
+          .hlen(&key)
+          .await
+          .map_err(|err| format!("Error saving message: {}", err))?;
+
+        if slots >= MAX_COPY_SLOTS {
+          return Err(format!(
+            "There can only be {} slots for each member. Reuse one of the existing ones",
+            MAX_COPY_SLOTS
+          ));
+        }
+      }
+
+      let _: () = conn
+        .hset(&key, slot, contents)
+        .await
+        .map_err(|err| format!("Error saving message: {}", err))?;
+
+      old_value
+    }
+    None => cmd("SET")
+      .arg(copy_key(user_id, guild_id))
+      .arg(contents)
+      .arg("GET")
+      .query_async(conn)
+      .await
+      .map_err(|err| format!("Error saving message: {}", err))?,
+  };
+
+  let history = history_key(user_id, guild_id);
+
+  let result: Result<(), _> = pipe()
+    .atomic()
+    .lpush(&history, contents)
+    .ignore()
+    .ltrim(&history, 0, MAX_COPY_HISTORY - 1)
+    .ignore()
+    .query_async(conn)
+    .await;
+
+  // The copy itself was saved, so this isn't worth failing over
+  if let Err(error) = result {
+    println!("Could not add to copy history: {}", error);
+  }
+
+  Ok(old_value)
 }
 
 pub async fn interaction_copy(
@@ -28,11 +178,6 @@ pub async fn interaction_copy(
     let message = message.unwrap();
 
     let contents = &message.content;
-    let key = format!(
-      "{}:{}:copy",
-      message.author.id.0,
-      interaction.guild_id.as_ref().unwrap().0
-    );
 
     let lock = {
       let mut context = ctx.data.write().await;
@@ -42,19 +187,20 @@ pub async fn interaction_copy(
         .clone()
     };
 
-    let old_value: Option<String> = {
+    let old_value = {
       let mut redis_client = lock.lock().await;
 
-      cmd("SET")
-        .arg(key)
-        .arg(contents)
-        .arg("GET")
-        .query_async(&mut redis_client.0)
-        .await
-        .map_err(|err| format!("Error saving message: {}", err.to_string()))?
+      save_copy(
+        &mut redis_client.0,
+        message.author.id.0,
+        interaction.guild_id.as_ref().unwrap().0,
+        None,
+        contents,
+      )
+      .await?
     };
 
-    let _ = interaction
+    interaction
       .create_interaction_response(ctx, |resp| {
         resp
           .kind(InteractionResponseType::ChannelMessageWithSource)
@@ -63,14 +209,15 @@ pub async fn interaction_copy(
               "{} set the copy for {} to \n> {}\n\nPrior message:\n> {}",
               interaction.user.mention(),
               message.author.mention(),
-              contents.replace("\n", "\n> "),
+              quote(contents),
               old_value
-                .map(|val| val.replace("\n", "\n> "))
+                .map(|val| quote(&val))
                 .unwrap_or(String::from("---"))
             ))
           })
       })
-      .await;
+      .await
+      .map_err(|err| format!("Could not show the copy: {}", err))?;
 
     Ok(())
   } else {
@@ -78,8 +225,270 @@ pub async fn interaction_copy(
   }
 }
 
+/// Asks which slot to copy a message to. The message is kept in Redis until
+/// then, since it can't be fetched again without the message content intent
+pub async fn interaction_copy_slot(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let message = match interaction.data.resolved.messages.values().next() {
+    Some(message) if interaction.guild_id.is_some() => message,
+    _ => return Err(String::from("Missing content/guild id")),
+  };
+
+  if message.content.trim().is_empty() {
+    return Err(String::from("That message has no text to copy"));
+  }
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  {
+    let mut redis_client = lock.lock().await;
+    let key = pending_key(interaction.id.0);
+
+    let result: Result<(), _> = pipe()
+      .atomic()
+      .hset(&key, "author", message.author.id.0)
+      .ignore()
+      .hset(&key, "content", &message.content)
+      .ignore()
+      .expire(&key, PENDING_COPY_SECONDS)
+      .ignore()
+      .query_async(&mut redis_client.0)
+      .await;
+
+    if let Err(error) = result {
+      return Err(format!("Error saving message: {}", error));
+    }
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::Modal)
+        .interaction_response_data(|msg| {
+          msg
+            .title("Copy to a slot")
+            .custom_id(format!("{}{}", COPY_SLOT_PREFIX, interaction.id.0))
+            .components(|comp| {
+              comp.create_action_row(|row| {
+                row.create_input_text(|text| {
+                  text
+                    .custom_id("slot")
+                    .label("Slot")
+                    .placeholder("A name to paste it by, like quote or excuse")
+                    .max_length(MAX_SLOT_NAME_LENGTH)
+                    .required(true)
+                    .style(InputTextStyle::Short)
+                })
+              })
+            })
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+pub async fn interaction_copy_slot_followup(
+  ctx: &Context,
+  modal: &ModalSubmitInteraction,
+) -> Result<(), String> {
+  let guild_id = match modal.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Err(String::from("Missing guild id")),
+  };
+
+  let interaction_id = modal
+    .data
+    .custom_id
+    .trim_start_matches(COPY_SLOT_PREFIX)
+    .parse::<u64>()
+    .map_err(|_| String::from("Invalid copy"))?;
+
+  let mut slot: Option<String> = None;
+
+  for row in &modal.data.components {
+    for component in &row.components {
+      if let ActionRowComponent::InputText(text) = component {
+        if text.custom_id == "slot" && !text.value.trim().is_empty() {
+          slot = Some(slot_name(&text.value));
+        }
+      }
+    }
+  }
+
+  let slot = slot.ok_or_else(|| String::from("You must provide a slot"))?;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let (user_id, contents, old_value) = {
+    let mut redis_client = lock.lock().await;
+    let key = pending_key(interaction_id);
+
+    let ((user_id, contents),): ((Option<u64>, Option<String>),) = pipe()
+      .atomic()
+      .hget(&key, &["author", "content"])
+      .del(&key)
+      .ignore()
+      .query_async(&mut redis_client.0)
+      .await
+      .map_err(|err| format!("Error retrieving message: {}", err))?;
+
+    let (user_id, contents) = match (user_id, contents) {
+      (Some(user_id), Some(contents)) => (user_id, contents),
+      _ => {
+        return Err(String::from(
+          "That took too long, so the message wasn't kept. Try again",
+        ))
+      }
+    };
+
+    let old_value = save_copy(
+      &mut redis_client.0,
+      user_id,
+      guild_id,
+      Some(&slot),
+      &contents,
+    )
+    .await?;
+
+    (user_id, contents, old_value)
+  };
+
+  modal
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg.content(format!(
+            "{} set the copy for {} in `{}` to \n> {}\n\nPrior message:\n> {}",
+            modal.user.mention(),
+            UserId(user_id).mention(),
+            slot,
+            quote(&contents),
+            old_value
+              .map(|val| quote(&val))
+              .unwrap_or(String::from("---"))
+          ))
+        })
+    })
+    .await
+    .map_err(|err| format!("Could not show the copy: {}", err))?;
+
+  Ok(())
+}
+
 pub fn paste_command(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
-  commands.create_application_command(|command| command.name("paste").kind(CommandType::User))
+  commands
+    .create_application_command(|command| command.name("paste").kind(CommandType::User))
+    .create_application_command(|command| {
+      command
+        .name("paste")
+        .description("Paste one of a member's copies, or pick from their latest ones")
+        .create_option(|member| {
+          member
+            .name("member")
+            .kind(CommandOptionType::User)
+            .description("Whose copy to paste")
+            .required(true)
+        })
+        .create_option(|slot| {
+          slot
+            .name("slot")
+            .kind(CommandOptionType::String)
+            .description("The slot to paste from")
+            .set_autocomplete(true)
+            .required(false)
+        })
+        .create_option(|index| {
+          index
+            .name("index")
+            .kind(CommandOptionType::Integer)
+            .description("Which of their latest copies to paste, 1 being the newest")
+            .min_int_value(1)
+            .max_int_value(MAX_COPY_HISTORY)
+            .required(false)
+        })
+    })
+}
+
+/// Their latest copies, newest first
+async fn get_history(
+  conn: &mut Connection,
+  user_id: u64,
+  guild_id: u64,
+) -> Result<Vec<String>, String> {
+  conn
+    .lrange(history_key(user_id, guild_id), 0, MAX_COPY_HISTORY - 1)
+    .await
+    .map_err(|err| format!("Error retrieving messages: {}", err))
+}
+
+fn preview(contents: &str) -> String {
+  let line = contents.lines().next().unwrap_or_default().trim();
+
+  if line.is_empty() {
+    String::from("(empty)")
+  } else if line.chars().count() > MAX_PREVIEW_LENGTH {
+    format!(
+      "{}…",
+      line
+        .chars()
+        .take(MAX_PREVIEW_LENGTH - 1)
+        .collect::<String>()
+    )
+  } else {
+    String::from(line)
+  }
+}
+
+/// A picker option's value: where the copy is in the history, and a short hash
+/// of it. The history can change before someone picks, so the hash makes sure
+/// it's still the same copy
+fn pick_value(index: usize, copy: &str) -> String {
+  let digest = Sha256::digest(copy.as_bytes());
+  let hash: String = digest[..4]
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect();
+
+  format!("{}:{}", index, hash)
+}
+
+async fn send_paste(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  name: &str,
+  copy: Option<String>,
+) -> Result<(), String> {
+  match copy {
+    Some(copy) => {
+      interaction
+        .create_interaction_response(ctx, |resp| {
+          resp
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|msg| msg.content(paste_message(name, &copy)))
+        })
+        .await
+        .map_err(|err| format!("Could not paste: {}", err))?;
+
+      Ok(())
+    }
+    None => Err(String::from("No copy saved")),
+  }
 }
 
 pub async fn interaction_paste(
@@ -93,8 +502,6 @@ pub async fn interaction_paste(
     let user = user.unwrap();
     let guild_id = guild_id.unwrap();
 
-    let key = format!("{}:{}:copy", user.id.0, guild_id.0);
-
     let lock = {
       let mut context = ctx.data.write().await;
       context
@@ -103,33 +510,259 @@ pub async fn interaction_paste(
         .clone()
     };
 
-    let copy: Option<String> = {
-      let mut redis_client = lock.lock().await;
+    if interaction.data.kind == CommandType::User {
+      let key = copy_key(user.id.0, guild_id.0);
 
-      redis_client
-        .0
-        .get(key)
-        .await
-        .map_err(|err| format!("Error retrieving message: {}", err.to_string()))?
-    };
+      let copy: Option<String> = {
+        let mut redis_client = lock.lock().await;
+
+        redis_client
+          .0
+          .get(key)
+          .await
+          .map_err(|err| format!("Error retrieving message: {}", err.to_string()))?
+      };
+
+      return send_paste(ctx, interaction, &user.name, copy).await;
+    }
+
+    let options = &interaction.data.options;
+
+    let slot = options
+      .iter()
+      .find(|option| option.name == "slot")
+      .and_then(|option| option.value.as_ref())
+      .and_then(|value| value.as_str())
+      .map(slot_name);
+
+    let index = options
+      .iter()
+      .find(|option| option.name == "index")
+      .and_then(|option| option.value.as_ref())
+      .and_then(|value| value.as_i64());
+
+    match (slot, index) {
+      (Some(_), Some(_)) => Err(String::from("Pick a slot or an index, not both")),
+      (Some(slot), None) => {
+        let copy: Option<String> = {
+          let mut redis_client = lock.lock().await;
+
+          redis_client
+            .0
+            .hget(slots_key(user.id.0, guild_id.0), &slot)
+            .await
+            .map_err(|err| format!("Error retrieving message: {}", err))?
+        };
+
+        match copy {
+          Some(_) => send_paste(ctx, interaction, &user.name, copy).await,
+          None => Err(format!("{} has nothing in `{}`", user.name, slot)),
+        }
+      }
+      (None, Some(index)) => {
+        let copy: Option<String> = {
+          let mut redis_client = lock.lock().await;
+
+          redis_client
+            .0
+            .lindex(history_key(user.id.0, guild_id.0), (index - 1) as isize)
+            .await
+            .map_err(|err| format!("Error retrieving message: {}", err))?
+        };
+
+        match copy {
+          Some(_) => send_paste(ctx, interaction, &user.name, copy).await,
+          None => Err(format!("{} doesn't have {} copies", user.name, index)),
+        }
+      }
+      (None, None) => {
+        let history = {
+          let mut redis_client = lock.lock().await;
+          get_history(&mut redis_client.0, user.id.0, guild_id.0).await?
+        };
+
+        if history.is_empty() {
+          return Err(String::from("No copy saved"));
+        }
 
-    match copy {
-      Some(copy) => {
         let _ = interaction
           .create_interaction_response(ctx, |resp| {
             resp
               .kind(InteractionResponseType::ChannelMessageWithSource)
               .interaction_response_data(|msg| {
-                msg.content(format!("Paste for {}: \n>>> {}", user.name, copy))
+                msg
+                  .content(format!(
+                    "Pick one of {}'s latest copies to paste",
+                    user.name
+                  ))
+                  .components(|comp| {
+                    comp.create_action_row(|row| {
+                      row.create_select_menu(|menu| {
+                        menu
+                          .custom_id(format!("{}{}", COPY_PICK_PREFIX, user.id.0))
+                          .placeholder("Newest first")
+                          .options(|opts| {
+                            for (idx, copy) in history.iter().enumerate() {
+                              opts.create_option(|opt| {
+                                opt
+                                  .label(preview(copy))
+                                  .value(pick_value(idx, copy))
+                                  .description(format!("Copy #{}", idx + 1))
+                              });
+                            }
+
+                            opts
+                          })
+                      })
+                    })
+                  })
+                  .ephemeral(true)
               })
           })
           .await;
 
         Ok(())
       }
-      None => Err(String::from("No copy saved")),
     }
   } else {
     Err(String::from("Missing user/guild id"))
   }
 }
+
+/// Pastes the copy picked from `/paste`'s menu for everyone to see
+pub async fn handle_copy_pick(
+  ctx: &Context,
+  interaction: &MessageComponentInteraction,
+) -> Result<(), String> {
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Err(String::from("Missing guild id")),
+  };
+
+  let user_id = interaction
+    .data
+    .custom_id
+    .trim_start_matches(COPY_PICK_PREFIX)
+    .parse::<u64>()
+    .map_err(|_| String::from("Invalid paste"))?;
+
+  let picked = interaction
+    .data
+    .values
+    .get(0)
+    .ok_or_else(|| String::from("You must pick a copy"))?;
+
+  let index = picked
+    .split(':')
+    .next()
+    .and_then(|index| index.parse::<usize>().ok())
+    .ok_or_else(|| String::from("Invalid paste"))?;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let copy: Option<String> = {
+    let mut redis_client = lock.lock().await;
+
+    redis_client
+      .0
+      .lindex(history_key(user_id, guild_id), index as isize)
+      .await
+      .map_err(|err| format!("Error retrieving message: {}", err))?
+  };
+
+  let copy = match copy {
+    Some(copy) if pick_value(index, &copy) == *picked => copy,
+    _ => {
+      return Err(String::from(
+        "Their copies changed since this was shown. Run `/paste` again",
+      ))
+    }
+  };
+
+  let name = match UserId(user_id).to_user(ctx).await {
+    Ok(user) => user.name,
+    Err(_) => String::from("someone"),
+  };
+
+  interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(paste_message(&name, &copy)))
+    })
+    .await
+    .map_err(|err| format!("Could not paste: {}", err))?;
+
+  Ok(())
+}
+
+pub async fn autocomplete_paste(
+  ctx: &Context,
+  interaction: &AutocompleteInteraction,
+) -> Result<(), String> {
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Ok(()),
+  };
+
+  let options = &interaction.data.options;
+
+  let user_id = options
+    .iter()
+    .find(|option| option.name == "member")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .and_then(|value| value.parse::<u64>().ok());
+
+  let typed = options
+    .iter()
+    .find(|option| option.focused)
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .map(slot_name)
+    .unwrap_or_default();
+
+  let slots: Vec<String> = match user_id {
+    Some(user_id) => {
+      let lock = {
+        let mut context = ctx.data.write().await;
+        context
+          .get_mut::<RedisConnectionKey>()
+          .expect("Expected redis connection")
+          .clone()
+      };
+
+      let mut redis_client = lock.lock().await;
+
+      redis_client
+        .0
+        .hkeys(slots_key(user_id, guild_id))
+        .await
+        .map_err(|err| err.to_string())?
+    }
+    // They haven't picked anyone yet
+    None => vec![],
+  };
+
+  let _ = interaction
+    .create_autocomplete_response(ctx, |response| {
+      for slot in slots
+        .iter()
+        .filter(|slot| slot.contains(&typed))
+        .take(MAX_COPY_SLOTS)
+      {
+        response.add_string_choice(slot, slot);
+      }
+
+      response
+    })
+    .await;
+
+  Ok(())
+}
@@ -55,13 +55,29 @@ pub async fn interaction_help(
         e.color(Color::BLITZ_BLUE)
           .title("Safety-chan Help!")
           .description(DESCRIPTION)
-            .field("/briefing", "Create a Safety Briefing. This opens a menu for you to post your news, to be send Monday at 7:30 AM Eastern", false)
-            .field("/poll new", "Create a new poll, with a set time, topic, and options. You can optionally allow others to add options later, but there is no editing or deleting of options (however, you can delete the entire poll)", false)
-            .field("/poll options_add", "Add an option to a poll. You can do this if you are the creator, or the poll is open", false)
-            .field("/roll", "Roll one or more dice", false)
+            .field("/briefing new", "Create a Safety Briefing. This opens a menu for you to post your news (with an optional category, like Event or PSA), to be sent with this server's next briefing (Monday at 7:30 AM Eastern unless it's been changed)", false)
+            .field("/briefing mine", "See your briefings that haven't been sent yet, with buttons to edit or delete them", false)
+            .field("/briefing attach", "Add an image to one of your briefings (or remove it), shown under it when it goes out", false)
+            .field("/briefing preview", "(Manage Server only) See what the next briefing will look like", false)
+            .field("/briefing archive", "See a briefing that has already gone out. Give it a `week` (any day that week), or leave it out to see which weeks there are", false)
+            .field("/briefing feed", "Get past briefings as an Atom feed file, to read them outside Discord", false)
+            .field("/briefing config", "(Manage Server only) Set the channel, day, time, timezone, header and role to ping for this server's briefing", false)
+            .field("/briefing moderation", "(Manage Server only) Send new briefings to a channel to be approved before they go out, or turn that off", false)
+            .field("/poll new", "Create a new poll, with a set time, topic, and options. You can optionally allow others to add options later, but there is no editing or deleting of options (however, you can delete the entire poll). `/poll options_add` adds an option, if you are the creator or the poll is open", false)
+            .field("/roll dice", "Roll one or more dice. You can add, subtract, multiply and divide dice and numbers, like 2d6+1d4+3 or (1d8+2)*2. 4d6dl drops the lowest die and 4d6dh the highest, while 4d6kh3 keeps the highest 3 and 4d6kl3 the lowest. adv and dis roll 2d20 with advantage or disadvantage. 4d6! explodes on a 6, 2d6r1 rerolls 1s (ro rerolls only once), 10d10>=7 counts the dice that roll 7 or more and 4dF rolls fudge dice. Set `verifiable` to roll with a secret seed whose hash you can see first with `/roll seed`. `/roll verify` reveals it later, so anyone can check the roll", false)
+            .field("/roll stats", "See the exact odds of a roll without rolling it: its average, spread and a chart of each total. Give a `target` to see the chance of rolling at least that", false)
+            .field("/roll history", "See the latest rolls in this channel (or just someone's). `/roll export` gets a session's rolls and each player's average and natural 20s as CSV files", false)
+            .field("/roll macro", "Save a roll you make a lot with `save` (everywhere, or just in this server), then roll it by name with `run`. `list` and `delete` manage them", false)
+            .field("/initiative", "Track turn order for a fight. `start` posts a tracker with Next and Previous buttons, `join` rolls a d20 plus your `modifier`, `add-npc` rolls for monsters, `next` moves to the next turn and `end` finishes it", false)
+            .field("/birthday set", "Set your birthday, to be announced in the server you run this in. Run it in each server you want it announced in. `/timezone set` sets your timezone, so it's announced on the right day where you live", false)
+            .field("/birthday upcoming", "See the next birthdays in this server. `/birthday export` gets them as a calendar file, to import into your calendar app", false)
+            .field("/birthday remind", "Get a DM a few days before someone's birthday (or everyone's), so you can plan ahead", false)
+            .field("/birthday config", "(Admins) Choose the channel, birthday role and announcement message for this server", false)
             .field("/nya", "Get a cat", false)
-            .field("/stats consent *", "This allows you to approve, delete, or revoke collecting of your emoji usage in a given server", false)
-            .field("/stats get", "If you have consented to collecting emoji stats, get your stats in a DM", false)
+            .field("/stats", "`consent` lets you approve, delete, or revoke collecting of your emoji usage in a given server. Once you have, `get` sends your stats in a DM", false)
+            .field("/starboard config", "(Manage Server only) Repost messages that get enough reactions of an emoji (⭐ by default) to a starboard channel, keeping their count up to date. `/starboard disable` turns it off", false)
+            .field("quote", "You can click on a message to add it to this server's quote book. `/quote random`, `/quote search` and `/quote by` read from it, and moderators can `/quote delete` one", false)
+            .field("copy", "You can click on a message to copy it, or use `copy to slot` to save it under a name. Click on a member and `paste` to paste their latest copy. `/paste` with a `slot` pastes from that slot, with an `index` pastes one of their latest copies (1 being the newest), and with neither lets you pick from their latest copies", false)
             .field("owo", "You can click on a message to see it owo-ified. I am not responsible for damages", false)
             .field("sanitize", "You can click on a message with a link, and it will strip out utm_ tracking", false)
         })
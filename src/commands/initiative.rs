@@ -0,0 +1,636 @@
+use crate::RedisConnectionKey;
+
+use rand::thread_rng;
+use redis::{aio::Connection, AsyncCommands, RedisError};
+use serde::{Deserialize, Serialize};
+use serenity::{
+  builder::{CreateApplicationCommands, CreateComponents, CreateEmbed},
+  model::{
+    application::{command::*, interaction::application_command::*},
+    prelude::{
+      component::ButtonStyle,
+      interaction::{message_component::MessageComponentInteraction, InteractionResponseType},
+      ChannelId,
+    },
+  },
+  prelude::*,
+  utils::Colour,
+};
+
+use super::util::get_str_or_error;
+use crate::util::dice::parse;
+
+pub const INITIATIVE_NEXT: &str = "initiative_next";
+pub const INITIATIVE_PREVIOUS: &str = "initiative_previous";
+
+/// Embed descriptions can be at most 4096 characters, so limit how many can
+/// be in the turn order
+const MAX_COMBATANTS: usize = 50;
+
+/// The most NPCs `/initiative add-npc` can add at once
+const MAX_NPCS: usize = 20;
+
+/// Names can be at most this long
+const MAX_NAME_LENGTH: u16 = 50;
+
+/// How long an initiative order is kept after it was last touched
+const ENCOUNTER_SECONDS: usize = 7 * 24 * 60 * 60;
+
+const NOT_STARTED: &str = "There's no initiative order here. Start one with `/initiative start`";
+
+pub fn initiative_command(
+  commands: &mut CreateApplicationCommands,
+) -> &mut CreateApplicationCommands {
+  commands.create_application_command(|command| {
+    command
+      .name("initiative")
+      .description("Keep track of initiative order for a tabletop session")
+      .create_option(|op| {
+        op.name("start")
+          .kind(CommandOptionType::SubCommand)
+          .description("Start a new initiative order in this channel")
+      })
+      .create_option(|op| {
+        op.name("join")
+          .kind(CommandOptionType::SubCommand)
+          .description("Roll initiative and join the order (or roll again)")
+          .create_sub_option(|modifier| {
+            modifier
+              .name("modifier")
+              .kind(CommandOptionType::Integer)
+              .description("Your initiative bonus, added to the d20 (default 0)")
+              .min_int_value(-100)
+              .max_int_value(100)
+              .required(false)
+          })
+          .create_sub_option(|name| {
+            name
+              .name("name")
+              .kind(CommandOptionType::String)
+              .description("Your character's name (default your name)")
+              .max_length(MAX_NAME_LENGTH)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("add-npc")
+          .kind(CommandOptionType::SubCommand)
+          .description("Roll initiative for monsters or other characters")
+          .create_sub_option(|name| {
+            name
+              .name("name")
+              .kind(CommandOptionType::String)
+              .description("What they are, like Goblin")
+              .max_length(MAX_NAME_LENGTH)
+              .required(true)
+          })
+          .create_sub_option(|modifier| {
+            modifier
+              .name("modifier")
+              .kind(CommandOptionType::Integer)
+              .description("Their initiative bonus, added to the d20 (default 0)")
+              .min_int_value(-100)
+              .max_int_value(100)
+              .required(false)
+          })
+          .create_sub_option(|count| {
+            count
+              .name("count")
+              .kind(CommandOptionType::Integer)
+              .description("How many of them there are, each rolling separately (default 1)")
+              .min_int_value(1)
+              .max_int_value(MAX_NPCS)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("next")
+          .kind(CommandOptionType::SubCommand)
+          .description("Move on to the next turn")
+      })
+      .create_option(|op| {
+        op.name("end")
+          .kind(CommandOptionType::SubCommand)
+          .description("End the initiative order in this channel")
+      })
+  })
+}
+
+/// Someone (or something) in the initiative order
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Combatant {
+  name: String,
+  /// Who they are, if they're a player
+  user_id: Option<u64>,
+  modifier: i64,
+  initiative: i64,
+}
+
+/// A channel's initiative order
+#[derive(Debug, Deserialize, Serialize)]
+struct Encounter {
+  /// Highest initiative first
+  combatants: Vec<Combatant>,
+  /// Whose turn it is, or `None` before the first turn
+  turn: Option<usize>,
+  round: u32,
+  /// The message showing the order, which is kept up to date
+  message_id: Option<u64>,
+}
+
+impl Encounter {
+  fn new() -> Encounter {
+    Encounter {
+      combatants: vec![],
+      turn: None,
+      round: 0,
+      message_id: None,
+    }
+  }
+
+  /// Adds `combatant` in order. Players replace their old roll, even under a
+  /// different name. Whoever's turn it is stays the same
+  fn insert(&mut self, combatant: Combatant) {
+    if let Some(existing) = combatant.user_id.and_then(|user_id| {
+      self
+        .combatants
+        .iter()
+        .position(|other| other.user_id == Some(user_id))
+    }) {
+      self.remove(existing);
+    }
+
+    // Ties go to the higher modifier, then alphabetically
+    let position = self
+      .combatants
+      .iter()
+      .position(|other| {
+        (other.initiative, other.modifier) < (combatant.initiative, combatant.modifier)
+          || ((other.initiative, other.modifier) == (combatant.initiative, combatant.modifier)
+            && other.name > combatant.name)
+      })
+      .unwrap_or(self.combatants.len());
+
+    self.combatants.insert(position, combatant);
+
+    if let Some(turn) = self.turn {
+      if position <= turn {
+        self.turn = Some(turn + 1);
+      }
+    }
+  }
+
+  /// A name for a new NPC that no one else in the order has. Ones that share a
+  /// name get numbers, carrying on from any already in the order
+  fn npc_name(&self, name: &str, numbered: bool) -> String {
+    let taken = |candidate: &str| self.combatants.iter().any(|other| other.name == candidate);
+
+    if !numbered && !taken(name) {
+      return String::from(name);
+    }
+
+    (if numbered { 1 } else { 2 }..)
+      .map(|number| format!("{} {}", name, number))
+      .find(|candidate| !taken(candidate))
+      .expect("Some number is free")
+  }
+
+  fn remove(&mut self, position: usize) {
+    self.combatants.remove(position);
+
+    self.turn = match self.turn {
+      _ if self.combatants.is_empty() => None,
+      Some(turn) if position < turn => Some(turn - 1),
+      // Whoever was after them goes next, wrapping around
+      Some(turn) if turn >= self.combatants.len() => Some(0),
+      turn => turn,
+    };
+  }
+
+  fn next(&mut self) {
+    if self.combatants.is_empty() {
+      return;
+    }
+
+    self.turn = match self.turn {
+      Some(turn) if turn + 1 < self.combatants.len() => Some(turn + 1),
+      _ => {
+        self.round += 1;
+        Some(0)
+      }
+    };
+  }
+
+  fn previous(&mut self) {
+    self.turn = match self.turn {
+      Some(turn) if turn > 0 => Some(turn - 1),
+      Some(_) if self.round > 1 => {
+        self.round -= 1;
+        Some(self.combatants.len() - 1)
+      }
+      _ => {
+        self.round = 0;
+        None
+      }
+    };
+  }
+
+  fn current(&self) -> Option<&Combatant> {
+    self.turn.and_then(|turn| self.combatants.get(turn))
+  }
+
+  fn to_embed(&self) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+
+    match self.turn {
+      Some(_) => embed.title(format!("Initiative: round {}", self.round)),
+      None => embed.title("Initiative: waiting to start"),
+    };
+
+    let mut description = String::new();
+
+    for (idx, combatant) in self.combatants.iter().enumerate() {
+      let line = format!("`{:>3}` {}", combatant.initiative, combatant.name);
+
+      if self.turn == Some(idx) {
+        description += &format!("▶ **{}**\n", line);
+      } else {
+        description += &format!("{}\n", line);
+      }
+    }
+
+    if description.is_empty() {
+      description = String::from("No one has joined yet");
+    }
+
+    embed
+      .description(description)
+      .colour(Colour::DARK_RED)
+      .footer(|footer| {
+        footer.text("Join with /initiative join, and add monsters with /initiative add-npc")
+      });
+
+    embed
+  }
+}
+
+fn components(comp: &mut CreateComponents) -> &mut CreateComponents {
+  comp.create_action_row(|row| {
+    row
+      .create_button(|button| {
+        button
+          .style(ButtonStyle::Secondary)
+          .label("Previous")
+          .custom_id(INITIATIVE_PREVIOUS)
+      })
+      .create_button(|button| {
+        button
+          .style(ButtonStyle::Primary)
+          .label("Next")
+          .custom_id(INITIATIVE_NEXT)
+      })
+  })
+}
+
+fn encounter_key(channel_id: u64) -> String {
+  format!("initiative:{}", channel_id)
+}
+
+async fn get_encounter(
+  conn: &mut Connection,
+  channel_id: u64,
+) -> Result<Option<Encounter>, String> {
+  let serialized: Option<String> = conn
+    .get(encounter_key(channel_id))
+    .await
+    .map_err(|err| format!("Could not get initiative order: {}", err))?;
+
+  match serialized {
+    Some(serialized) => serde_json::from_str(&serialized)
+      .map(Some)
+      .map_err(|err| format!("Could not read initiative order: {}", err)),
+    None => Ok(None),
+  }
+}
+
+async fn save_encounter(
+  conn: &mut Connection,
+  channel_id: u64,
+  encounter: &Encounter,
+) -> Result<(), String> {
+  let serialized = serde_json::to_string(encounter).expect("Encounters are serializable");
+
+  let result: Result<(), RedisError> = conn
+    .set_ex(encounter_key(channel_id), serialized, ENCOUNTER_SECONDS)
+    .await;
+
+  result.map_err(|err| format!("Could not save initiative order: {}", err))
+}
+
+/// Rolls 1d20 plus `modifier`, returning the total and how it went
+fn roll_initiative(modifier: i64) -> Result<(i64, String), String> {
+  let expr = parse(&format!("1d20{:+}", modifier)).map_err(|error| error.message)?;
+  let roll = expr.roll(&mut thread_rng())?;
+
+  Ok((roll.total, roll.breakdown))
+}
+
+/// Shows the latest order on the tracker message (if there is one)
+async fn update_tracker(ctx: &Context, channel_id: u64, encounter: &Encounter) {
+  if let Some(message_id) = encounter.message_id {
+    let _ = ChannelId(channel_id)
+      .edit_message(ctx, message_id, |msg| {
+        msg.set_embed(encounter.to_embed()).components(components)
+      })
+      .await;
+  }
+}
+
+pub async fn interaction_initiative(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let subcommand = match interaction.data.options.get(0) {
+    Some(subcommand) => subcommand,
+    None => return Err(String::from("Must have subcommand")),
+  };
+
+  let channel_id = interaction.channel_id.0;
+
+  let option = |name: &str| {
+    subcommand
+      .options
+      .iter()
+      .find(|option| option.name == name)
+      .and_then(|option| option.value.clone())
+  };
+
+  let modifier = option("modifier")
+    .and_then(|modifier| modifier.as_i64())
+    .unwrap_or_default();
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let mut redis_client = lock.lock().await;
+
+  let existing = get_encounter(&mut redis_client.0, channel_id).await?;
+
+  if subcommand.name == "start" {
+    if existing.is_some() {
+      return Err(String::from(
+        "There's already an initiative order here. End it with `/initiative end` first",
+      ));
+    }
+
+    let mut encounter = Encounter::new();
+
+    // Saved before responding, so no one else can start one meanwhile
+    save_encounter(&mut redis_client.0, channel_id, &encounter).await?;
+    drop(redis_client);
+
+    let _ = interaction
+      .create_interaction_response(ctx, |response| {
+        response
+          .kind(InteractionResponseType::ChannelMessageWithSource)
+          .interaction_response_data(|msg| {
+            msg
+              .content("Roll for initiative! Join with `/initiative join`")
+              .set_embed(encounter.to_embed())
+              .components(components)
+          })
+      })
+      .await;
+
+    let message_id = match interaction.get_interaction_response(ctx).await {
+      Ok(message) => message.id.0,
+      Err(_) => return Ok(()),
+    };
+
+    // Someone may have joined (or ended it) while this was being sent
+    let mut redis_client = lock.lock().await;
+
+    encounter = match get_encounter(&mut redis_client.0, channel_id).await? {
+      Some(encounter) if encounter.message_id.is_none() => encounter,
+      _ => return Ok(()),
+    };
+
+    encounter.message_id = Some(message_id);
+    save_encounter(&mut redis_client.0, channel_id, &encounter).await?;
+    drop(redis_client);
+
+    // Show anyone who joined before the tracker was saved
+    if !encounter.combatants.is_empty() {
+      update_tracker(ctx, channel_id, &encounter).await;
+    }
+
+    return Ok(());
+  }
+
+  let mut encounter = match existing {
+    Some(encounter) => encounter,
+    None => return Err(String::from(NOT_STARTED)),
+  };
+
+  let message = match subcommand.name.as_str() {
+    "join" => {
+      let name = match option("name").and_then(|name| name.as_str().map(String::from)) {
+        Some(name) => name.trim().to_string(),
+        None => interaction
+          .member
+          .as_ref()
+          .and_then(|member| member.nick.clone())
+          .unwrap_or_else(|| interaction.user.name.clone()),
+      };
+
+      // Rolling again replaces your old roll, so it doesn't take another place
+      let rejoining = encounter
+        .combatants
+        .iter()
+        .any(|combatant| combatant.user_id == Some(interaction.user.id.0));
+
+      if !rejoining && encounter.combatants.len() >= MAX_COMBATANTS {
+        return Err(format!(
+          "There can only be {} in the initiative order",
+          MAX_COMBATANTS
+        ));
+      }
+
+      let (initiative, breakdown) = roll_initiative(modifier)?;
+
+      encounter.insert(Combatant {
+        name: name.clone(),
+        user_id: Some(interaction.user.id.0),
+        modifier,
+        initiative,
+      });
+
+      format!(
+        "**{}** rolled **{}** for initiative ({})",
+        name, initiative, breakdown
+      )
+    }
+    "add-npc" => {
+      let name = get_str_or_error(&option("name"), "You must give the NPC a name")?;
+      let name = name.trim();
+
+      let count = option("count")
+        .and_then(|count| count.as_u64())
+        .map_or(1, |count| (count as usize).min(MAX_NPCS));
+
+      if encounter.combatants.len() + count > MAX_COMBATANTS {
+        return Err(format!(
+          "There can only be {} in the initiative order",
+          MAX_COMBATANTS
+        ));
+      }
+
+      let mut rolled: Vec<String> = vec![];
+
+      for _ in 0..count {
+        let npc_name = encounter.npc_name(name, count > 1);
+
+        let (initiative, _) = roll_initiative(modifier)?;
+        rolled.push(format!("**{}**: {}", npc_name, initiative));
+
+        encounter.insert(Combatant {
+          name: npc_name,
+          user_id: None,
+          modifier,
+          initiative,
+        });
+      }
+
+      format!("Rolled initiative for {}", rolled.join(", "))
+    }
+    "next" => {
+      encounter.next();
+
+      match encounter.current() {
+        Some(Combatant {
+          user_id: Some(user_id),
+          name,
+          ..
+        }) => format!(
+          "Round {}: it's <@{}>'s turn ({})",
+          encounter.round, user_id, name
+        ),
+        Some(combatant) => format!(
+          "Round {}: it's **{}**'s turn",
+          encounter.round, combatant.name
+        ),
+        None => String::from("No one has joined yet"),
+      }
+    }
+    "end" => {
+      let result: Result<(), RedisError> = redis_client.0.del(encounter_key(channel_id)).await;
+
+      if let Err(error) = result {
+        return Err(format!("Could not end initiative order: {}", error));
+      }
+
+      drop(redis_client);
+
+      if let Some(message_id) = encounter.message_id {
+        let _ = ChannelId(channel_id)
+          .edit_message(ctx, message_id, |msg| {
+            msg
+              .content(format!("Initiative ended after {} rounds", encounter.round))
+              .set_embed(encounter.to_embed())
+              .components(|comp| comp)
+          })
+          .await;
+      }
+
+      let _ = interaction
+        .create_interaction_response(ctx, |response| {
+          response
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|msg| msg.content("Ended the initiative order"))
+        })
+        .await;
+
+      return Ok(());
+    }
+    _ => return Err(String::from("Unexpected command")),
+  };
+
+  save_encounter(&mut redis_client.0, channel_id, &encounter).await?;
+  drop(redis_client);
+
+  update_tracker(ctx, channel_id, &encounter).await;
+
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message))
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Handles the Next and Previous buttons on the tracker
+pub async fn handle_initiative_turn(
+  ctx: &Context,
+  interaction: &MessageComponentInteraction,
+) -> Result<(), String> {
+  let channel_id = interaction.channel_id.0;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let encounter = {
+    let mut redis_client = lock.lock().await;
+
+    let mut encounter = match get_encounter(&mut redis_client.0, channel_id).await? {
+      // Old trackers stop working once there's a new one
+      Some(encounter) if encounter.message_id == Some(interaction.message.id.0) => encounter,
+      _ => return Err(String::from("This initiative order has ended")),
+    };
+
+    if interaction.data.custom_id == INITIATIVE_NEXT {
+      encounter.next();
+    } else {
+      encounter.previous();
+    }
+
+    save_encounter(&mut redis_client.0, channel_id, &encounter).await?;
+    encounter
+  };
+
+  let content = match encounter.current() {
+    Some(Combatant {
+      user_id: Some(user_id),
+      ..
+    }) => format!("<@{}>, it's your turn", user_id),
+    Some(combatant) => format!("It's **{}**'s turn", combatant.name),
+    None => String::from("Roll for initiative! Join with `/initiative join`"),
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::UpdateMessage)
+        .interaction_response_data(|msg| {
+          msg
+            .content(content)
+            .set_embed(encounter.to_embed())
+            .components(components)
+        })
+    })
+    .await;
+
+  Ok(())
+}
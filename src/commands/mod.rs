@@ -4,10 +4,14 @@ pub mod util;
 pub mod birthday;
 pub mod copy;
 pub mod help;
+pub mod initiative;
 pub mod link;
 pub mod news;
 pub mod nya;
 pub mod owo;
 pub mod poll;
+pub mod quote;
 pub mod roll;
+pub mod starboard;
+pub mod timezone;
 pub mod unshittify;
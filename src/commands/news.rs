@@ -1,229 +1,2526 @@
-use crate::{MyVec, RedisConnectionKey};
-use std::sync::Arc;
+use crate::{
+  util::{
+    checkpoint::{advance_field_checkpoint, release_field_checkpoint},
+    leader::Lease,
+    rng::random_id,
+  },
+  RedisConnectionKey, RedisWrapper,
+};
+use std::{collections::HashMap, future::Future, sync::Arc};
 
-use chrono::Utc;
-use chrono_tz::EST5EDT;
-use redis::AsyncCommands;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::{Tz, EST5EDT};
+use redis::{aio::Connection, cmd, pipe, AsyncCommands, RedisResult};
 use serde::{Deserialize, Serialize};
 use serenity::{
-  builder::CreateApplicationCommands,
+  builder::{CreateApplicationCommands, CreateEmbed, CreateInteractionResponseData},
   http::Http,
   model::{
-    application::interaction::{application_command::*, *},
+    application::{
+      command::*,
+      interaction::{application_command::*, *},
+    },
     prelude::{
-      component::{ActionRowComponent, InputTextStyle},
-      interaction::modal::ModalSubmitInteraction,
-      ChannelId, Guild,
+      component::{ActionRowComponent, ButtonStyle, InputTextStyle},
+      interaction::{
+        message_component::MessageComponentInteraction, modal::ModalSubmitInteraction,
+      },
+      ChannelId, Guild, GuildId, Member, UserId,
     },
   },
   prelude::*,
 };
 
+/// A briefing entry. These are stored as JSON (rather than bincode) so fields
+/// can be added later without breaking entries that are already queued
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Briefing {
+  #[serde(default)]
+  pub id: String,
   pub author: String,
+  #[serde(default)]
+  pub author_id: u64,
+  /// The guild whose briefing this goes out in
+  #[serde(default)]
+  pub guild_id: u64,
   pub heading: String,
   pub body: Option<String>,
+  #[serde(default)]
+  pub category: Option<String>,
+  /// The URL of an image shown under this entry
+  #[serde(default)]
+  pub image: Option<String>,
+  /// Where the bot re-uploaded the image, as (channel, message). Discord's
+  /// attachment links expire, so `image` is refreshed from this before it's shown
+  #[serde(default)]
+  pub image_message: Option<(u64, u64)>,
   pub time: String,
+  /// Whether this can go out. Only false while moderation is on and nobody
+  /// has reviewed it yet
+  #[serde(default = "default_approved")]
+  pub approved: bool,
+  /// Goes up whenever the author changes it, so review buttons for an older
+  /// version can't approve this one
+  #[serde(default)]
+  pub revision: u32,
+  /// The review message for the current revision, as (channel, message)
+  #[serde(default)]
+  pub review_message: Option<(u64, u64)>,
+}
+
+fn default_approved() -> bool {
+  true
+}
+
+/// What someone filled in on the briefing modal
+struct BriefingInput {
+  heading: String,
+  body: Option<String>,
+  category: Option<String>,
+}
+
+/// One embed of a posted briefing
+struct DigestEmbed {
+  title: String,
+  description: String,
+  fields: Vec<(String, String)>,
+  image: Option<String>,
+}
+
+impl DigestEmbed {
+  fn new(title: &str) -> DigestEmbed {
+    DigestEmbed {
+      title: String::from(title),
+      description: String::new(),
+      fields: vec![],
+      image: None,
+    }
+  }
+
+  /// How many characters this counts for towards a message's embed limit
+  fn len(&self) -> usize {
+    self.title.chars().count()
+      + self.description.chars().count()
+      + self
+        .fields
+        .iter()
+        .map(|(name, value)| name.chars().count() + value.chars().count())
+        .sum::<usize>()
+  }
+
+  fn to_embed(&self) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.title(&self.title);
+
+    if !self.description.is_empty() {
+      embed.description(&self.description);
+    }
+
+    for (name, value) in &self.fields {
+      embed.field(name, value, false);
+    }
+
+    if let Some(image) = &self.image {
+      embed.image(image);
+    }
+
+    embed
+  }
+}
+
+/// How briefings were stored before they had IDs. Only used for migrating
+#[derive(Deserialize)]
+struct LegacyBriefing {
+  author: String,
+  heading: String,
+  body: Option<String>,
+  time: String,
+}
+
+/// How a guild runs its briefing. Stored as JSON, one per guild
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BriefingConfig {
+  pub channel: u64,
+  /// Days after Monday
+  pub weekday: u32,
+  pub hour: u32,
+  pub minute: u32,
+  pub timezone: String,
+  /// The embed title. `{date}` is replaced with the date of the issue
+  pub header: String,
+  #[serde(default)]
+  pub ping_role: Option<u64>,
+  /// Where submissions are sent for review. When this is not set, briefings
+  /// go out without being approved
+  #[serde(default)]
+  pub review_channel: Option<u64>,
+}
+
+impl BriefingConfig {
+  /// The original schedule: Mondays at 7:30 AM Eastern
+  fn new(channel: u64) -> BriefingConfig {
+    BriefingConfig {
+      channel,
+      weekday: 0,
+      hour: 7,
+      minute: 30,
+      timezone: String::from(EST5EDT.name()),
+      header: String::from(DEFAULT_BRIEFING_HEADER),
+      ping_role: None,
+      review_channel: None,
+    }
+  }
+
+  pub fn timezone(&self) -> Tz {
+    self.timezone.parse::<Tz>().unwrap_or(EST5EDT)
+  }
+
+  /// When the issue that goes out on `date` is sent
+  fn issue_time_on(&self, date: NaiveDate) -> DateTime<Tz> {
+    let tz = self.timezone();
+    let local =
+      date.and_time(NaiveTime::from_hms_opt(self.hour, self.minute, 0).unwrap_or_default());
+
+    // If the clocks skip over the issue time that day, send it an hour later
+    tz.from_local_datetime(&local)
+      .earliest()
+      .or_else(|| {
+        tz.from_local_datetime(&(local + Duration::hours(1)))
+          .earliest()
+      })
+      .unwrap_or_else(|| tz.from_utc_datetime(&local))
+  }
+
+  /// Gets the time of the most recent issue at or before `now`
+  pub fn latest_issue_time(&self, now: DateTime<Utc>) -> DateTime<Tz> {
+    let today = now.with_timezone(&self.timezone()).date_naive();
+    let days_back = (7 + today.weekday().num_days_from_monday() - self.weekday % 7) % 7;
+    let date = today - Duration::days(days_back.into());
+
+    let issue = self.issue_time_on(date);
+
+    if issue > now {
+      self.issue_time_on(date - Duration::weeks(1))
+    } else {
+      issue
+    }
+  }
+
+  pub fn next_issue_time(&self, now: DateTime<Utc>) -> DateTime<Tz> {
+    let latest = self.latest_issue_time(now);
+    self.issue_time_on(latest.date_naive() + Duration::weeks(1))
+  }
+
+  pub fn header_for(&self, issue: DateTime<Tz>) -> String {
+    self
+      .header
+      .replace("{date}", &issue.format("%B %-d").to_string())
+  }
+
+  fn describe(&self, now: DateTime<Utc>) -> String {
+    let mut description = format!(
+      "Briefings go out in <#{}> every {} at {}:{:02} ({}). The next one is <t:{}:F>",
+      self.channel,
+      WEEKDAYS[(self.weekday % 7) as usize],
+      self.hour,
+      self.minute,
+      self.timezone,
+      self.next_issue_time(now).timestamp()
+    );
+
+    if let Some(role) = self.ping_role {
+      description += &format!("\n<@&{}> is pinged when it goes out", role);
+    }
+
+    if let Some(channel) = self.review_channel {
+      description += &format!("\nNew briefings are reviewed in <#{}>", channel);
+    }
+
+    description
+  }
 }
 
+/// The guild that briefings submitted in DMs go to
 pub struct BriefingGuildKey;
 impl TypeMapKey for BriefingGuildKey {
   type Value = u64;
 }
 
-pub const BRIEFING_KEY: &str = "safety:briefings";
+/// Where briefings used to be stored: a ZSET of bincode briefings
+const LEGACY_BRIEFING_KEY: &str = "safety:briefings";
+
+/// Pending briefing IDs, scored by when they were submitted
+pub const BRIEFING_QUEUE_KEY: &str = "safety:briefings:queue";
+
+/// Pending briefings (as JSON), by ID
+pub const BRIEFING_ENTRIES_KEY: &str = "safety:briefings:entries";
+
+/// Each guild's `BriefingConfig`
+pub const BRIEFING_CONFIG_KEY: &str = "safety:briefings:config";
+
+/// The timestamp of the last issue each guild was sent
+const BRIEFING_SENT_KEY: &str = "safety:briefings:sent";
+
+/// Where the last issue sent was kept, back when there was only one guild
+const LEGACY_CHECKPOINT_KEY: &str = "safety:briefings:last";
+
+/// Where the review channel was kept, back when there was only one guild
+const LEGACY_REVIEW_CHANNEL_KEY: &str = "safety:briefings:review_channel";
+
+const WEEKDAYS: [&str; 7] = [
+  "Monday",
+  "Tuesday",
+  "Wednesday",
+  "Thursday",
+  "Friday",
+  "Saturday",
+  "Sunday",
+];
+
+/// Embed titles can be at most 256 characters
+const MAX_HEADER_LENGTH: usize = 256;
+
+/// Added to the titles of the parts after the first of a long issue
+const CONTINUED_SUFFIX: &str = " (continued)";
+
+/// The longest date `header_for` can fill in
+const LONGEST_HEADER_DATE: &str = "September 30";
+
+/// Suggested categories, in the order their sections appear. People can also
+/// make up their own, which go after these
+const BRIEFING_CATEGORIES: [&str; 3] = ["Life update", "Event", "PSA"];
+
+/// The section for briefings without a category, which goes first
+const UNCATEGORIZED_SECTION: &str = "News";
+
+const MAX_CATEGORY_LENGTH: u64 = 30;
+
+// Discord's limits on embeds
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const EMBED_FIELD_VALUE_LIMIT: usize = 1024;
+const EMBED_FIELD_LIMIT: usize = 25;
+const MESSAGE_EMBED_LIMIT: usize = 10;
+const MESSAGE_EMBED_TOTAL_LIMIT: usize = 6000;
+
+/// How many of your briefings `/briefing mine` can show buttons for
+const MAX_LISTED_BRIEFINGS: usize = 5;
+
+/// The largest image that can be attached to a briefing, in bytes. Bots can
+/// upload 8 MB anywhere
+const MAX_IMAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+pub const BRIEFING_EDIT_PREFIX: &str = "briefing_edit:";
+pub const BRIEFING_DELETE_PREFIX: &str = "briefing_delete:";
+pub const BRIEFING_APPROVE_PREFIX: &str = "briefing_approve:";
+pub const BRIEFING_REJECT_PREFIX: &str = "briefing_reject:";
+
+const NOT_SET_UP: &str =
+  "Briefings aren't set up in this server. Someone with Manage Server can use `/briefing config`";
+
+const DEFAULT_BRIEFING_HEADER: &str =
+  "*DOOTDOOTDOOTDOOT*. It's time for your Safety Weekly Briefing!";
 
 pub fn news_command(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
-  commands
-    .create_application_command(|command| command.name("briefing").description("Start a briefing"))
+  commands.create_application_command(|command| {
+    command
+      .name("briefing")
+      .description("Start a briefing")
+      .create_option(|op| {
+        op.name("new")
+          .kind(CommandOptionType::SubCommand)
+          .description("Write a new briefing")
+      })
+      .create_option(|op| {
+        op.name("mine")
+          .kind(CommandOptionType::SubCommand)
+          .description("See, edit or delete your briefings that haven't been sent yet")
+      })
+      .create_option(|op| {
+        op.name("attach")
+          .kind(CommandOptionType::SubCommand)
+          .description("Add an image to one of your briefings")
+          .create_sub_option(|id| {
+            id.name("id")
+              .kind(CommandOptionType::String)
+              .description("The ID of the briefing (from /briefing mine)")
+              .required(true)
+          })
+          .create_sub_option(|image| {
+            image
+              .name("image")
+              .kind(CommandOptionType::Attachment)
+              .description("The image to show under it")
+              .required(false)
+          })
+          .create_sub_option(|remove| {
+            remove
+              .name("remove")
+              .kind(CommandOptionType::Boolean)
+              .description("Remove the image instead")
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("preview")
+          .kind(CommandOptionType::SubCommand)
+          .description("See what the next briefing will look like (Manage Server only)")
+      })
+      .create_option(|op| {
+        op.name("moderation")
+          .kind(CommandOptionType::SubCommand)
+          .description("Review briefings before they go out (Manage Server only)")
+          .create_sub_option(|channel| {
+            channel
+              .name("review_channel")
+              .kind(CommandOptionType::Channel)
+              .description("Where new briefings are sent to be approved")
+              .required(false)
+          })
+          .create_sub_option(|off| {
+            off
+              .name("off")
+              .kind(CommandOptionType::Boolean)
+              .description("Stop reviewing briefings, so new ones go straight in")
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("archive")
+          .kind(CommandOptionType::SubCommand)
+          .description("See a briefing that has already gone out")
+          .create_sub_option(|week| {
+            week
+              .name("week")
+              .kind(CommandOptionType::String)
+              .description(
+                "Any day in the week it went out, like 2024-03-04. Leave out to list them",
+              )
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("feed")
+          .kind(CommandOptionType::SubCommand)
+          .description("Get past briefings as a feed (Atom) file, for reading outside Discord")
+      })
+      .create_option(|op| {
+        op.name("config")
+          .kind(CommandOptionType::SubCommand)
+          .description("Set up this server's briefing (Manage Server only)")
+          .create_sub_option(|channel| {
+            channel
+              .name("channel")
+              .kind(CommandOptionType::Channel)
+              .description("Where the briefing is posted")
+              .required(false)
+          })
+          .create_sub_option(|day| {
+            day
+              .name("day")
+              .kind(CommandOptionType::String)
+              .description("What day of the week it goes out")
+              .required(false);
+
+            for weekday in WEEKDAYS {
+              day.add_string_choice(weekday, weekday);
+            }
+
+            day
+          })
+          .create_sub_option(|time| {
+            time
+              .name("time")
+              .kind(CommandOptionType::String)
+              .description("What time it goes out (24 hour, like 7:30 or 19:30)")
+              .required(false)
+          })
+          .create_sub_option(|zone| {
+            zone
+              .name("timezone")
+              .kind(CommandOptionType::String)
+              .description("The timezone for the time, like America/Chicago")
+              .required(false)
+          })
+          .create_sub_option(|header| {
+            header
+              .name("header")
+              .kind(CommandOptionType::String)
+              .description("The title of the post. {date} is the date. Use \"default\" to reset")
+              .required(false)
+          })
+          .create_sub_option(|role| {
+            role
+              .name("ping_role")
+              .kind(CommandOptionType::Role)
+              .description("A role to ping when it goes out")
+              .required(false)
+          })
+          .create_sub_option(|remove| {
+            remove
+              .name("remove_ping")
+              .kind(CommandOptionType::Boolean)
+              .description("Stop pinging a role")
+              .required(false)
+          })
+      })
+  })
 }
 
-pub async fn interaction_briefing(
+/// Runs a subcommand that can take longer than the few seconds Discord waits
+/// for a response. It answers with a followup, and so do its errors, since the
+/// deferred response is all main could have sent them in
+async fn answer_later(
   ctx: &Context,
   interaction: &ApplicationCommandInteraction,
+  command: impl Future<Output = Result<(), String>>,
 ) -> Result<(), String> {
-  let _ = interaction
-    .create_interaction_response(ctx, |response| {
-      response
-        .kind(InteractionResponseType::Modal)
-        .interaction_response_data(|msg| {
-          msg
-            .ephemeral(true)
-            .title("Create a new briefing")
-            .custom_id("briefing")
-            .components(|comp| {
-              comp
-                .create_action_row(|row| {
-                  row.create_input_text(|text| {
-                    text
-                      .custom_id("title")
-                      .label("Your briefing header!")
-                      .placeholder("Something newsworthy *dootdootdootdoot*")
-                      .required(true)
-                      .style(InputTextStyle::Short)
-                      .max_length(100)
-                  })
-                })
-                .create_action_row(|row| {
-                  row.create_input_text(|text| {
-                    text
-                      .custom_id("content")
-                      .label("Give us the deets (or don't; that's ok too)")
-                      .placeholder("(This is optional)")
-                      .required(false)
-                      .style(InputTextStyle::Paragraph)
-                      .max_length(3800)
-                  })
-                })
-            })
-        })
+  interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.ephemeral(true))
     })
-    .await;
+    .await
+    .map_err(|err| format!("Could not respond: {}", err))?;
+
+  if let Err(error) = command.await {
+    let _ = interaction
+      .create_followup_message(ctx, |msg| {
+        msg
+          .content(format!("An error occurred: {}", error))
+          .ephemeral(true)
+      })
+      .await;
+  }
 
   Ok(())
 }
 
-pub async fn interaction_briefing_followup(
+pub async fn interaction_briefing(
   ctx: &Context,
-  modal: &ModalSubmitInteraction,
+  interaction: &ApplicationCommandInteraction,
 ) -> Result<(), String> {
+  let subcommand = interaction
+    .data
+    .options
+    .get(0)
+    .map_or("new", |option| option.name.as_str());
+
+  match subcommand {
+    "new" => {
+      let _ = interaction
+        .create_interaction_response(ctx, |response| {
+          response
+            .kind(InteractionResponseType::Modal)
+            .interaction_response_data(|msg| {
+              briefing_modal(msg, "briefing", "Create a new briefing", None)
+            })
+        })
+        .await;
+
+      Ok(())
+    }
+    "mine" => my_briefings(ctx, interaction).await,
+    "attach" => answer_later(ctx, interaction, attach_briefing(ctx, interaction)).await,
+    "preview" => preview_briefing(ctx, interaction).await,
+    "moderation" => moderate_briefings(ctx, interaction).await,
+    "config" => config_briefing(ctx, interaction).await,
+    "archive" => answer_later(ctx, interaction, archive_briefing(ctx, interaction)).await,
+    "feed" => answer_later(ctx, interaction, briefing_feed(ctx, interaction)).await,
+    _ => Err(String::from("Unexpected command")),
+  }
+}
+
+/// Fills in the briefing modal, optionally prefilled with an existing briefing
+fn briefing_modal<'a, 'b>(
+  msg: &'b mut CreateInteractionResponseData<'a>,
+  custom_id: &str,
+  title: &str,
+  existing: Option<&Briefing>,
+) -> &'b mut CreateInteractionResponseData<'a> {
+  msg
+    .ephemeral(true)
+    .title(title)
+    .custom_id(custom_id)
+    .components(|comp| {
+      comp
+        .create_action_row(|row| {
+          row.create_input_text(|text| {
+            text
+              .custom_id("title")
+              .label("Your briefing header!")
+              .placeholder("Something newsworthy *dootdootdootdoot*")
+              .required(true)
+              .style(InputTextStyle::Short)
+              .max_length(100);
+
+            if let Some(briefing) = existing {
+              text.value(&briefing.heading);
+            }
+
+            text
+          })
+        })
+        .create_action_row(|row| {
+          row.create_input_text(|text| {
+            text
+              .custom_id("category")
+              .label("Category")
+              .placeholder(format!("Optional, like {}", BRIEFING_CATEGORIES.join(", ")))
+              .required(false)
+              .style(InputTextStyle::Short)
+              .max_length(MAX_CATEGORY_LENGTH);
+
+            if let Some(category) = existing.and_then(|briefing| briefing.category.as_ref()) {
+              text.value(category);
+            }
+
+            text
+          })
+        })
+        .create_action_row(|row| {
+          row.create_input_text(|text| {
+            text
+              .custom_id("content")
+              .label("Give us the deets (or don't; that's ok too)")
+              .placeholder("(This is optional)")
+              .required(false)
+              .style(InputTextStyle::Paragraph)
+              .max_length(3800);
+
+            if let Some(body) = existing.and_then(|briefing| briefing.body.as_ref()) {
+              text.value(body);
+            }
+
+            text
+          })
+        })
+    })
+}
+
+/// Matches a category to one of the suggested ones if it is one (ignoring
+/// case), so they all end up in the same section
+fn normalize_category(input: &str) -> Option<String> {
+  let category = input.trim();
+
+  if category.is_empty() {
+    return None;
+  }
+
+  let known = BRIEFING_CATEGORIES
+    .iter()
+    .find(|known| known.eq_ignore_ascii_case(category));
+
+  Some(String::from(*known.unwrap_or(&category)))
+}
+
+/// Gets what was filled in on a submitted briefing modal
+fn read_briefing_modal(modal: &ModalSubmitInteraction) -> Result<BriefingInput, String> {
   let mut heading: Option<String> = None;
   let mut body: Option<String> = None;
+  let mut category: Option<String> = None;
 
   for row in &modal.data.components {
     for component in &row.components {
       if let ActionRowComponent::InputText(text) = component {
         if text.custom_id == "title" {
           heading = Some(text.value.clone());
-        } else if text.custom_id == "content" {
+        } else if text.custom_id == "content" && !text.value.trim().is_empty() {
           body = Some(text.value.clone());
+        } else if text.custom_id == "category" {
+          category = normalize_category(&text.value);
         }
       }
     }
   }
 
-  if heading == None {
-    return Err(String::from("You must provide a heading"));
+  match heading {
+    Some(heading) => Ok(BriefingInput {
+      heading,
+      body,
+      category,
+    }),
+    None => Err(String::from("You must provide a heading")),
   }
+}
 
-  let guild_id = {
-    ctx
-      .data
-      .read()
-      .await
-      .get::<BriefingGuildKey>()
-      .expect("Expected guild id")
-      .clone()
-  };
+pub async fn interaction_briefing_followup(
+  ctx: &Context,
+  modal: &ModalSubmitInteraction,
+) -> Result<(), String> {
+  let input = read_briefing_modal(modal)?;
 
-  match Guild::get(&ctx, guild_id).await {
-    Ok(guild) => {
-      if let Err(_) = guild.member(&ctx, modal.user.id).await {
-        return Err(String::from("You are not approved"));
+  let guild_id = match modal.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => {
+      let guild_id = {
+        ctx
+          .data
+          .read()
+          .await
+          .get::<BriefingGuildKey>()
+          .expect("Expected guild id")
+          .clone()
+      };
+
+      match Guild::get(&ctx, guild_id).await {
+        Ok(guild) => {
+          if let Err(_) = guild.member(&ctx, modal.user.id).await {
+            return Err(String::from("You are not approved"));
+          }
+        }
+        Err(error) => return Err(error.to_string()),
       }
+
+      guild_id
     }
-    Err(error) => return Err(error.to_string()),
-  }
+  };
 
-  let now = Utc::now().with_timezone(&EST5EDT);
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
 
-  let briefing = Briefing {
-    author: modal.user.mention().to_string(),
-    heading: heading.unwrap().to_string(),
-    body: body,
-    time: now.format("%A %B %-d, %Y %-I:%M %P").to_string(),
+  let config = {
+    let mut redis_client = lock.lock().await;
+    get_config(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Could not save your briefing: {}", err))?
   };
 
-  let serialized = match bincode::serialize(&briefing) {
-    Ok(data) => data,
-    Err(_) => {
-      return Err(String::from(
-        "Could not serialize your briefing. Please but bot manager",
-      ));
-    }
+  let config = match config {
+    Some(config) => config,
+    None => return Err(String::from(NOT_SET_UP)),
   };
 
-  let result = {
-    let lock = {
-      let mut context = ctx.data.write().await;
-      context
-        .get_mut::<RedisConnectionKey>()
-        .expect("Expected redis connection")
-        .clone()
-    };
+  let review_channel = config.review_channel;
+  let now = Utc::now().with_timezone(&config.timezone());
+
+  let mut briefing = Briefing {
+    id: String::new(),
+    author: modal.user.mention().to_string(),
+    author_id: modal.user.id.0,
+    guild_id,
+    heading: input.heading,
+    body: input.body,
+    category: input.category,
+    image: None,
+    image_message: None,
+    time: now.format("%A %B %-d, %Y %-I:%M %P").to_string(),
+    approved: review_channel.is_none(),
+    revision: 0,
+    review_message: None,
+  };
 
+  let result = {
     let mut redis_client = lock.lock().await;
-    redis_client
-      .0
-      .zadd::<&str, i64, Vec<u8>, u64>(BRIEFING_KEY, serialized, now.timestamp())
-      .await
+    add_briefing(&mut redis_client.0, &mut briefing, now.timestamp()).await
   };
 
-  match result {
-    Ok(_) => {
-      let _ = modal
-        .create_interaction_response(ctx, |resp| {
-          resp
-            .kind(InteractionResponseType::ChannelMessageWithSource)
-            .interaction_response_data(|msg| msg.content("Submitted").ephemeral(true))
-        })
-        .await;
+  if let Err(error) = result {
+    return Err(format!("Could not save your briefing: {:?}", error));
+  }
+
+  let mut content = format!(
+    "Submitted (ID {}). Use `/briefing mine` to change it before it goes out",
+    briefing.id
+  );
+
+  if let Some(channel_id) = review_channel {
+    // Nobody could approve it, so it would never go out. Take it back out of
+    // the queue so it can just be submitted again
+    if let Err(error) = send_for_review(ctx, &mut briefing, channel_id).await {
+      let mut redis_client = lock.lock().await;
+
+      return match remove_briefings(&mut redis_client.0, &[briefing.id.clone()]).await {
+        Ok(()) => Err(format!("{}. It was not submitted, so try again", error)),
+        Err(remove_error) => Err(format!(
+          "{}. It is still waiting (ID {}), but nobody can approve it, so delete it from `/briefing mine` and try again ({})",
+          error, briefing.id, remove_error
+        )),
+      };
     }
-    Err(error) => return Err(format!("Could not save your briefing: {:?}", error)),
-  };
+
+    content += "\nA moderator has to approve it first";
+  }
+
+  let _ = modal
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(content).ephemeral(true))
+    })
+    .await;
 
   Ok(())
 }
 
-pub async fn send_briefing(
-  briefings: &MyVec,
+/// Gets fresh links for images the bot re-uploaded, since the old ones expire.
+/// If the upload is gone, the last link is kept
+async fn refresh_images(http: &Http, briefings: &mut [Briefing]) {
+  for briefing in briefings {
+    if let Some((channel_id, message_id)) = briefing.image_message {
+      if let Ok(message) = ChannelId(channel_id).message(http, message_id).await {
+        if let Some(attachment) = message.attachments.first() {
+          briefing.image = Some(attachment.url.clone());
+        }
+      }
+    }
+  }
+}
+
+/// The part of a review button's ID after its prefix. This includes the
+/// revision, so buttons stop working once the briefing changes
+fn review_id(briefing: &Briefing) -> String {
+  format!("{}:{}", briefing.id, briefing.revision)
+}
+
+/// Posts a briefing to the review channel, with buttons to approve or reject
+/// it, and remembers where the review is
+async fn send_for_review(
+  ctx: &Context,
+  briefing: &mut Briefing,
   channel_id: u64,
-  http: &Arc<Http>,
 ) -> Result<(), String> {
-  let mut messages: Vec<String> = vec![];
-  let mut current_message = String::from("");
+  refresh_images(&ctx.http, std::slice::from_mut(briefing)).await;
 
-  for briefing in &briefings.v {
-    let brief: Briefing = match bincode::deserialize(&briefing) {
-      Ok(data) => data,
-      Err(error) => return Err(format!("Could not deserialize briefing: {:?}", error)),
-    };
+  let result = ChannelId(channel_id)
+    .send_message(ctx, |m| {
+      m.content(format!(
+        "New briefing from {} needs review",
+        briefing.author
+      ))
+      .embed(|e| {
+        e.title(&briefing.heading)
+          .description(briefing.body.as_deref().unwrap_or("*(No details)*"))
+          .field(
+            "Category",
+            briefing
+              .category
+              .as_deref()
+              .unwrap_or(UNCATEGORIZED_SECTION),
+            true,
+          )
+          .footer(|f| f.text(format!("ID {}", briefing.id)));
 
-    let next_message = match brief.body {
-      Some(ref body) => format!(
-        concat!("**{}**\n", "```{}```", " - {} {}\n\n",),
-        brief.heading, body, brief.author, brief.time
-      ),
-      None => format!(
-        concat!("**{}**\n", " - {} {}\n\n"),
-        brief.heading, brief.author, brief.time
-      ),
-    };
+        if let Some(image) = &briefing.image {
+          e.image(image);
+        }
 
-    if current_message.len() + next_message.len() < 2000 {
-      current_message += &next_message;
-    } else {
-      messages.push(current_message);
-      current_message = next_message;
-    }
+        e
+      })
+      .components(|comp| {
+        comp.create_action_row(|row| {
+          row
+            .create_button(|button| {
+              button
+                .style(ButtonStyle::Success)
+                .label("Approve")
+                .custom_id(format!(
+                  "{}{}",
+                  BRIEFING_APPROVE_PREFIX,
+                  review_id(briefing)
+                ))
+            })
+            .create_button(|button| {
+              button
+                .style(ButtonStyle::Danger)
+                .label("Reject")
+                .custom_id(format!("{}{}", BRIEFING_REJECT_PREFIX, review_id(briefing)))
+            })
+        })
+      })
+    })
+    .await;
+
+  let message =
+    result.map_err(|error| format!("Could not send your briefing for review: {}", error))?;
+
+  briefing.review_message = Some((channel_id, message.id.0));
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  // Re-read it, so this can't undo an approval made since the review went out
+  let mut redis_client = lock.lock().await;
+
+  let saved = get_briefing(&mut redis_client.0, &briefing.id)
+    .await
+    .map_err(|err| format!("Could not save your briefing: {}", err))?;
+
+  if let Some(mut saved) = saved.filter(|saved| saved.revision == briefing.revision) {
+    saved.review_message = briefing.review_message;
+    update_briefing(&mut redis_client.0, &saved)
+      .await
+      .map_err(|err| format!("Could not save your briefing: {}", err))?;
   }
 
-  let header = String::from("*DOOTDOOTDOOTDOOT*. It's time for your Safety Weekly Briefing!");
+  Ok(())
+}
 
-  messages.push(current_message);
-  let channel = ChannelId(channel_id);
+/// Takes the buttons off a review that's out of date
+async fn close_review(ctx: &Context, (channel_id, message_id): (u64, u64)) {
+  let _ = ChannelId(channel_id)
+    .edit_message(ctx, message_id, |msg| {
+      msg
+        .content("This briefing was changed after this was sent. Review the newer version instead")
+        .components(|comp| comp)
+    })
+    .await;
+}
 
-  for message in messages {
-    if let Err(error) = channel
-      .send_message(http, |m| {
-        m.embed(|e| e.title(header.clone()).description(message))
-      })
+fn is_moderator(member: Option<&Member>) -> bool {
+  member
+    .and_then(|member| member.permissions)
+    .map_or(false, |permissions| permissions.manage_guild())
+}
+
+pub async fn handle_briefing_review(
+  ctx: &Context,
+  interaction: &MessageComponentInteraction,
+) -> Result<(), String> {
+  if !is_moderator(interaction.member.as_ref()) {
+    return Err(String::from(
+      "You need the Manage Server permission to review briefings",
+    ));
+  }
+
+  let custom_id = interaction.data.custom_id.as_str();
+  let (approve, review_id) = match custom_id.strip_prefix(BRIEFING_APPROVE_PREFIX) {
+    Some(id) => (true, id),
+    None => (false, custom_id.trim_start_matches(BRIEFING_REJECT_PREFIX)),
+  };
+
+  // Reviews sent before revisions were tracked are for revision 0
+  let (id, revision) = match review_id.rsplit_once(':') {
+    Some((id, revision)) => (id, revision.parse::<u32>().unwrap_or(u32::MAX)),
+    None => (review_id, 0),
+  };
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let outcome = {
+    let mut redis_client = lock.lock().await;
+
+    let briefing = get_briefing(&mut redis_client.0, id)
       .await
-    {
-      return Err(format!("Could not send briefing: {:?}", error));
+      .map_err(|err| format!("Could not get briefing: {}", err))?;
+
+    match briefing {
+      Some(briefing) if briefing.revision != revision => String::from(
+        "This briefing was changed after this was sent. Review the newer version instead",
+      ),
+      Some(mut briefing) if approve => {
+        briefing.approved = true;
+        update_briefing(&mut redis_client.0, &briefing)
+          .await
+          .map_err(|err| format!("Could not approve briefing: {}", err))?;
+        format!("Approved by {}", interaction.user.mention())
+      }
+      Some(briefing) => {
+        remove_briefings(&mut redis_client.0, &[briefing.id])
+          .await
+          .map_err(|err| format!("Could not reject briefing: {}", err))?;
+        format!("Rejected by {}", interaction.user.mention())
+      }
+      None => String::from("This briefing has already been sent or deleted"),
     }
-  }
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::UpdateMessage)
+        .interaction_response_data(|msg| msg.content(outcome).components(|comp| comp))
+    })
+    .await;
+
+  Ok(())
+}
+
+async fn preview_briefing(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  if !is_moderator(interaction.member.as_ref()) {
+    return Err(String::from(
+      "You need the Manage Server permission to do this",
+    ));
+  }
+
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let (config, briefings) = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+
+    let config = get_config(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Could not get briefing settings: {}", err))?;
+
+    let briefings = get_pending_briefings(&mut redis_client.0, "+inf")
+      .await
+      .map_err(|err| format!("Could not get briefings: {}", err))?;
+
+    (config, briefings)
+  };
+
+  let config = match config {
+    Some(config) => config,
+    None => return Err(String::from(NOT_SET_UP)),
+  };
+
+  let (mut approved, waiting): (Vec<Briefing>, Vec<Briefing>) = briefings
+    .into_iter()
+    .filter(|briefing| briefing.guild_id == guild_id)
+    .partition(|briefing| briefing.approved);
+
+  if approved.is_empty() {
+    return Err(format!(
+      "Nothing is in the next briefing yet ({} waiting for approval)",
+      waiting.len()
+    ));
+  }
+
+  refresh_images(&ctx.http, &mut approved).await;
+
+  let next_issue = config.next_issue_time(Utc::now());
+  let header = config.header_for(next_issue);
+  let posts = briefing_digest(&header, &approved);
+
+  let mut content = format!(
+    "This is what the briefing going out <t:{}:F> will look like",
+    next_issue.timestamp()
+  );
+
+  if !waiting.is_empty() {
+    content += &format!(
+      ". {} more waiting for approval are not included",
+      waiting.len()
+    );
+  }
+
+  if posts.len() > 1 {
+    content += &format!(
+      ". It will be sent as {} messages, and this is the first",
+      posts.len()
+    );
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg.content(content).ephemeral(true).set_embeds(
+            posts[0]
+              .iter()
+              .map(DigestEmbed::to_embed)
+              .collect::<Vec<_>>(),
+          )
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+async fn moderate_briefings(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  if !is_moderator(interaction.member.as_ref()) {
+    return Err(String::from(
+      "You need the Manage Server permission to do this",
+    ));
+  }
+
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let mut channel: Option<u64> = None;
+  let mut off = false;
+
+  for option in &interaction.data.options[0].options {
+    match (option.name.as_str(), &option.value) {
+      ("review_channel", Some(value)) => {
+        channel = value.as_str().and_then(|id| id.parse::<u64>().ok());
+      }
+      ("off", Some(value)) => off = value.as_bool() == Some(true),
+      _ => {}
+    }
+  }
+
+  let message = match (channel, off) {
+    (Some(channel_id), false) => format!(
+      "New briefings will be sent to <#{}> and need to be approved",
+      channel_id
+    ),
+    (None, true) => {
+      String::from("New briefings go straight in. Ones already waiting still need to be approved")
+    }
+    _ => {
+      return Err(String::from(
+        "Either give a review channel or turn moderation off",
+      ))
+    }
+  };
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  {
+    let mut redis_client = lock.lock().await;
+
+    let mut config = match get_config(&mut redis_client.0, guild_id).await {
+      Ok(Some(config)) => config,
+      Ok(None) => return Err(String::from(NOT_SET_UP)),
+      Err(error) => return Err(error.to_string()),
+    };
+
+    config.review_channel = channel;
+
+    if let Err(error) = save_config(&mut redis_client.0, guild_id, &config).await {
+      return Err(error.to_string());
+    }
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Parses a 24 hour time, like 7:30 or 19:05
+fn parse_issue_time(input: &str) -> Option<(u32, u32)> {
+  let (hour, minute) = input.trim().split_once(':')?;
+  let hour = hour.parse::<u32>().ok()?;
+  let minute = minute.parse::<u32>().ok()?;
+
+  if hour < 24 && minute < 60 {
+    Some((hour, minute))
+  } else {
+    None
+  }
+}
+
+async fn config_briefing(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  if !is_moderator(interaction.member.as_ref()) {
+    return Err(String::from(
+      "You need the Manage Server permission to do this",
+    ));
+  }
+
+  let guild_id = interaction.guild_id.unwrap().0;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let existing = {
+    let mut redis_client = lock.lock().await;
+    get_config(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Could not get briefing settings: {}", err))?
+  };
+
+  let options = &interaction.data.options[0].options;
+
+  let channel = options
+    .iter()
+    .find(|option| option.name == "channel")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .and_then(|id| id.parse::<u64>().ok());
+
+  let mut config = match (existing, channel) {
+    (Some(config), _) => config,
+    (None, Some(channel)) => BriefingConfig::new(channel),
+    (None, None) => {
+      return Err(String::from(
+        "Briefings aren't set up here yet, so you need to give a channel",
+      ))
+    }
+  };
+
+  for option in options {
+    let value = match &option.value {
+      Some(value) => value,
+      None => continue,
+    };
+
+    match option.name.as_str() {
+      "channel" => {
+        if let Some(channel) = channel {
+          config.channel = channel;
+        }
+      }
+      "day" => {
+        let day = value.as_str().unwrap_or_default();
+
+        config.weekday = match WEEKDAYS.iter().position(|weekday| *weekday == day) {
+          Some(weekday) => weekday as u32,
+          None => return Err(format!("'{}' is not a day of the week", day)),
+        };
+      }
+      "time" => {
+        let time = value.as_str().unwrap_or_default();
+
+        match parse_issue_time(time) {
+          Some((hour, minute)) => {
+            config.hour = hour;
+            config.minute = minute;
+          }
+          None => {
+            return Err(format!(
+              "'{}' is not a time. Use 24 hour time, like 7:30 or 19:30",
+              time
+            ))
+          }
+        }
+      }
+      "timezone" => {
+        let zone = value.as_str().unwrap_or_default().trim();
+
+        match zone.parse::<Tz>() {
+          Ok(tz) => config.timezone = String::from(tz.name()),
+          Err(_) => {
+            return Err(format!(
+              "'{}' is not a timezone I know. Try something like America/New_York",
+              zone
+            ))
+          }
+        }
+      }
+      "header" => {
+        let header = value.as_str().unwrap_or_default();
+
+        if header.trim() == "default" {
+          config.header = String::from(DEFAULT_BRIEFING_HEADER);
+        } else if header
+          .replace("{date}", LONGEST_HEADER_DATE)
+          .chars()
+          .count()
+          > MAX_HEADER_LENGTH - CONTINUED_SUFFIX.len()
+        {
+          // Long issues repeat the header with a suffix, which has to fit too
+          return Err(format!(
+            "Headers can be at most {} characters once the date is filled in",
+            MAX_HEADER_LENGTH - CONTINUED_SUFFIX.len()
+          ));
+        } else {
+          config.header = String::from(header);
+        }
+      }
+      "ping_role" => {
+        config.ping_role = value.as_str().and_then(|id| id.parse::<u64>().ok());
+      }
+      "remove_ping" => {
+        if value.as_bool() == Some(true) {
+          config.ping_role = None;
+        }
+      }
+      _ => {}
+    }
+  }
+
+  {
+    let mut redis_client = lock.lock().await;
+
+    if let Err(error) = save_config(&mut redis_client.0, guild_id, &config).await {
+      return Err(error.to_string());
+    }
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(config.describe(Utc::now())).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+async fn my_briefings(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let briefings = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    get_pending_briefings(&mut redis_client.0, "+inf")
+      .await
+      .map_err(|err| format!("Could not get your briefings: {}", err))?
+  };
+
+  let mine: Vec<Briefing> = briefings
+    .into_iter()
+    .filter(|briefing| briefing.author_id == interaction.user.id.0)
+    .collect();
+
+  if mine.is_empty() {
+    return Err(String::from(
+      "You don't have any briefings waiting to be sent. Use `/briefing new` to write one",
+    ));
+  }
+
+  let mut content = String::from("Your briefings that haven't gone out yet:\n");
+
+  for briefing in &mine {
+    content += &format!(
+      "\n`{}` **{}** [{}] ({}){}{}",
+      briefing.id,
+      briefing.heading,
+      briefing
+        .category
+        .as_deref()
+        .unwrap_or(UNCATEGORIZED_SECTION),
+      briefing.time,
+      if briefing.image.is_some() {
+        " with an image"
+      } else {
+        ""
+      },
+      if briefing.approved {
+        ""
+      } else {
+        " - waiting for approval"
+      }
+    );
+  }
+
+  if mine.len() > MAX_LISTED_BRIEFINGS {
+    content += &format!(
+      "\n\nOnly the first {} can be changed here",
+      MAX_LISTED_BRIEFINGS
+    );
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg.content(content).ephemeral(true).components(|comp| {
+            for briefing in mine.iter().take(MAX_LISTED_BRIEFINGS) {
+              comp.create_action_row(|row| {
+                row
+                  .create_button(|button| {
+                    button
+                      .style(ButtonStyle::Primary)
+                      .label(format!("Edit {}", briefing.id))
+                      .custom_id(format!("{}{}", BRIEFING_EDIT_PREFIX, briefing.id))
+                  })
+                  .create_button(|button| {
+                    button
+                      .style(ButtonStyle::Danger)
+                      .label(format!("Delete {}", briefing.id))
+                      .custom_id(format!("{}{}", BRIEFING_DELETE_PREFIX, briefing.id))
+                  })
+              });
+            }
+
+            comp
+          })
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Gets a pending briefing, making sure it belongs to `user_id`
+async fn get_own_briefing(ctx: &Context, id: &str, user_id: u64) -> Result<Briefing, String> {
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let briefing = {
+    let mut redis_client = lock.lock().await;
+    get_briefing(&mut redis_client.0, id)
+      .await
+      .map_err(|err| format!("Could not get briefing: {}", err))?
+  };
+
+  match briefing {
+    Some(briefing) if briefing.author_id == user_id => Ok(briefing),
+    Some(_) => Err(String::from("You can only change your own briefings")),
+    None => Err(String::from(
+      "That briefing has already been sent or deleted",
+    )),
+  }
+}
+
+/// Saves changes someone made to their own briefing. If the guild reviews
+/// briefings, it has to be approved again (so nothing slips in after review),
+/// and this returns true
+async fn save_changed_briefing(ctx: &Context, briefing: &mut Briefing) -> Result<bool, String> {
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let (updated, review_channel, old_review) = {
+    let mut redis_client = lock.lock().await;
+
+    let review_channel = get_config(&mut redis_client.0, briefing.guild_id)
+      .await
+      .map_err(|err| format!("Could not save your briefing: {}", err))?
+      .and_then(|config| config.review_channel);
+    briefing.approved = review_channel.is_none();
+    briefing.revision += 1;
+    let old_review = briefing.review_message.take();
+
+    let updated = update_briefing(&mut redis_client.0, briefing)
+      .await
+      .map_err(|err| format!("Could not save your briefing: {}", err))?;
+
+    (updated, review_channel, old_review)
+  };
+
+  if !updated {
+    return Err(String::from(
+      "That briefing has already been sent or deleted",
+    ));
+  }
+
+  if let Some(old_review) = old_review {
+    close_review(ctx, old_review).await;
+  }
+
+  match review_channel {
+    Some(channel_id) => {
+      send_for_review(ctx, briefing, channel_id).await?;
+      Ok(true)
+    }
+    None => Ok(false),
+  }
+}
+
+async fn attach_briefing(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options[0].options;
+
+  let id = options
+    .iter()
+    .find(|option| option.name == "id")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .map(str::trim)
+    .unwrap_or_default();
+
+  let remove = options
+    .iter()
+    .find(|option| option.name == "remove")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_bool())
+    .unwrap_or(false);
+
+  let mut briefing = get_own_briefing(ctx, id, interaction.user.id.0).await?;
+
+  let mut content = if remove {
+    briefing.image = None;
+    briefing.image_message = None;
+    format!("Removed the image from **{}**", briefing.heading)
+  } else {
+    let attachment = match interaction.data.resolved.attachments.values().next() {
+      Some(attachment) => attachment,
+      None => {
+        return Err(String::from(
+          "You must give an image, or remove the one there",
+        ))
+      }
+    };
+
+    let is_image = attachment
+      .content_type
+      .as_ref()
+      .map_or(false, |content_type| content_type.starts_with("image/"));
+
+    if !is_image {
+      return Err(format!("{} is not an image", attachment.filename));
+    }
+
+    if attachment.size > MAX_IMAGE_SIZE {
+      return Err(format!(
+        "Images can be at most {} MB",
+        MAX_IMAGE_SIZE / 1024 / 1024
+      ));
+    }
+
+    // Links to slash command attachments are signed and expire (usually well
+    // before the briefing goes out), so the image is uploaded again as a
+    // message the bot owns. Its link is refreshed from that message when shown
+    let bytes = attachment
+      .download()
+      .await
+      .map_err(|err| format!("Could not download {}: {}", attachment.filename, err))?;
+
+    let dm = interaction
+      .user
+      .create_dm_channel(ctx)
+      .await
+      .map_err(|err| format!("Could not keep your image: {}", err))?;
+
+    let kept = dm
+      .send_message(ctx, |m| {
+        m.content(format!(
+          "This is the image for your briefing **{}** (ID {}). I keep it here so it doesn't expire before the briefing goes out",
+          briefing.heading, briefing.id
+        ))
+        .add_file((bytes.as_slice(), attachment.filename.as_str()))
+      })
+      .await
+      .map_err(|err| format!("Could not keep your image (I need to be able to DM you): {}", err))?;
+
+    let url = kept
+      .attachments
+      .first()
+      .map(|kept| kept.url.clone())
+      .ok_or_else(|| String::from("Could not keep your image"))?;
+
+    briefing.image = Some(url);
+    briefing.image_message = Some((dm.id.0, kept.id.0));
+    format!("Added {} to **{}**", attachment.filename, briefing.heading)
+  };
+
+  if save_changed_briefing(ctx, &mut briefing).await? {
+    content += ". A moderator has to approve it again";
+  }
+
+  interaction
+    .create_followup_message(ctx, |msg| msg.content(content).ephemeral(true))
+    .await
+    .map_err(|err| format!("Could not respond: {}", err))?;
+
+  Ok(())
+}
+
+pub async fn handle_briefing_edit(
+  ctx: &Context,
+  interaction: &MessageComponentInteraction,
+) -> Result<(), String> {
+  let id = interaction
+    .data
+    .custom_id
+    .trim_start_matches(BRIEFING_EDIT_PREFIX);
+
+  let briefing = get_own_briefing(ctx, id, interaction.user.id.0).await?;
+
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::Modal)
+        .interaction_response_data(|msg| {
+          briefing_modal(
+            msg,
+            &format!("{}{}", BRIEFING_EDIT_PREFIX, briefing.id),
+            "Edit your briefing",
+            Some(&briefing),
+          )
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+pub async fn interaction_briefing_edit_followup(
+  ctx: &Context,
+  modal: &ModalSubmitInteraction,
+) -> Result<(), String> {
+  let id = modal
+    .data
+    .custom_id
+    .trim_start_matches(BRIEFING_EDIT_PREFIX);
+  let input = read_briefing_modal(modal)?;
+
+  let mut briefing = get_own_briefing(ctx, id, modal.user.id.0).await?;
+  briefing.heading = input.heading;
+  briefing.body = input.body;
+  briefing.category = input.category;
+
+  let mut content = format!("Updated briefing {}", briefing.id);
+
+  if save_changed_briefing(ctx, &mut briefing).await? {
+    content += ". A moderator has to approve it again";
+  }
+
+  let _ = modal
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(content).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+pub async fn handle_briefing_delete(
+  ctx: &Context,
+  interaction: &MessageComponentInteraction,
+) -> Result<(), String> {
+  let id = interaction
+    .data
+    .custom_id
+    .trim_start_matches(BRIEFING_DELETE_PREFIX);
+
+  let briefing = get_own_briefing(ctx, id, interaction.user.id.0).await?;
+
+  {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    remove_briefings(&mut redis_client.0, &[briefing.id.clone()])
+      .await
+      .map_err(|err| format!("Could not delete your briefing: {}", err))?;
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content(format!("Deleted briefing **{}**", briefing.heading))
+            .ephemeral(true)
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Queues a new briefing, giving it an unused ID
+async fn add_briefing(
+  con: &mut Connection,
+  briefing: &mut Briefing,
+  timestamp: i64,
+) -> RedisResult<()> {
+  loop {
+    briefing.id = random_id();
+
+    let serialized = serde_json::to_string(&briefing).expect("Briefings are serializable");
+
+    let added: bool = con
+      .hset_nx(BRIEFING_ENTRIES_KEY, &briefing.id, serialized)
+      .await?;
+
+    if added {
+      return con.zadd(BRIEFING_QUEUE_KEY, &briefing.id, timestamp).await;
+    }
+  }
+}
+
+/// Saves changes to a briefing. Returns false if it is no longer pending
+async fn update_briefing(con: &mut Connection, briefing: &Briefing) -> RedisResult<bool> {
+  let serialized = serde_json::to_string(&briefing).expect("Briefings are serializable");
+
+  // HSET has no XX flag, so only overwrite if the entry is still there
+  let exists: bool = con.hexists(BRIEFING_ENTRIES_KEY, &briefing.id).await?;
+
+  if exists {
+    con
+      .hset(BRIEFING_ENTRIES_KEY, &briefing.id, serialized)
+      .await?;
+  }
+
+  Ok(exists)
+}
+
+async fn get_config(con: &mut Connection, guild_id: u64) -> RedisResult<Option<BriefingConfig>> {
+  let config: Option<String> = con.hget(BRIEFING_CONFIG_KEY, guild_id).await?;
+
+  Ok(config.and_then(|config| serde_json::from_str(&config).ok()))
+}
+
+async fn save_config(
+  con: &mut Connection,
+  guild_id: u64,
+  config: &BriefingConfig,
+) -> RedisResult<()> {
+  let serialized = serde_json::to_string(config).expect("Configs are serializable");
+  con.hset(BRIEFING_CONFIG_KEY, guild_id, serialized).await
+}
+
+async fn get_briefing(con: &mut Connection, id: &str) -> RedisResult<Option<Briefing>> {
+  let entry: Option<String> = con.hget(BRIEFING_ENTRIES_KEY, id).await?;
+
+  Ok(entry.and_then(|entry| serde_json::from_str(&entry).ok()))
+}
+
+/// Gets every queued briefing submitted at or before `until` (a timestamp, or
+/// `+inf`), oldest first
+async fn get_pending_briefings<T: redis::ToRedisArgs + Send + Sync>(
+  con: &mut Connection,
+  until: T,
+) -> RedisResult<Vec<Briefing>> {
+  let ids: Vec<String> = con.zrangebyscore(BRIEFING_QUEUE_KEY, "-inf", until).await?;
+
+  if ids.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let entries: Vec<Option<String>> = cmd("HMGET")
+    .arg(BRIEFING_ENTRIES_KEY)
+    .arg(&ids)
+    .query_async(con)
+    .await?;
+
+  Ok(
+    entries
+      .into_iter()
+      .flatten()
+      .filter_map(|entry| serde_json::from_str(&entry).ok())
+      .collect(),
+  )
+}
+
+async fn remove_briefings(con: &mut Connection, ids: &[String]) -> RedisResult<()> {
+  if ids.is_empty() {
+    return Ok(());
+  }
+
+  pipe()
+    .atomic()
+    .zrem(BRIEFING_QUEUE_KEY, ids)
+    .ignore()
+    .hdel(BRIEFING_ENTRIES_KEY, ids)
+    .ignore()
+    .query_async(con)
+    .await
+}
+
+/// Moves briefings from older formats into the current one:
+/// - Briefings used to be stored without IDs, as a ZSET of bincode
+/// - There used to be a single briefing, for `default_guild`, posted to
+///   `default_channel` on a fixed schedule
+pub async fn migrate_briefings(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  default_guild: u64,
+  default_channel: u64,
+) -> RedisResult<()> {
+  let mut redis_client = connection.lock().await;
+  let con = &mut redis_client.0;
+
+  let mut config = BriefingConfig::new(default_channel);
+  config.review_channel = con.get(LEGACY_REVIEW_CHANNEL_KEY).await?;
+
+  let serialized = serde_json::to_string(&config).expect("Configs are serializable");
+  con
+    .hset_nx(BRIEFING_CONFIG_KEY, default_guild, serialized)
+    .await?;
+
+  let last_sent: Option<i64> = con.get(LEGACY_CHECKPOINT_KEY).await?;
+
+  if let Some(last_sent) = last_sent {
+    con
+      .hset_nx(BRIEFING_SENT_KEY, default_guild, last_sent)
+      .await?;
+  }
+
+  con
+    .del(&[LEGACY_CHECKPOINT_KEY, LEGACY_REVIEW_CHANNEL_KEY])
+    .await?;
+
+  // Entries from before there were guilds belong to the default one
+  let entries: Vec<(String, String)> = con.hgetall(BRIEFING_ENTRIES_KEY).await?;
+
+  for (id, entry) in entries {
+    if let Ok(mut briefing) = serde_json::from_str::<Briefing>(&entry) {
+      if briefing.guild_id == 0 {
+        briefing.guild_id = default_guild;
+        update_briefing(con, &briefing).await?;
+      }
+    } else {
+      println!("Could not read briefing {}", id);
+    }
+  }
+
+  let exists: bool = con.exists(LEGACY_BRIEFING_KEY).await?;
+
+  if !exists {
+    return Ok(());
+  }
+
+  // Renaming first means only one instance will ever migrate these
+  let temp_key = format!("{}:migrating", LEGACY_BRIEFING_KEY);
+  let renamed: RedisResult<()> = con.rename(LEGACY_BRIEFING_KEY, &temp_key).await;
+
+  if renamed.is_err() {
+    return Ok(());
+  }
+
+  let legacy: Vec<(Vec<u8>, i64)> = con.zrange_withscores(&temp_key, 0, -1).await?;
+
+  for (data, timestamp) in legacy {
+    let old: LegacyBriefing = match bincode::deserialize(&data) {
+      Ok(old) => old,
+      Err(error) => {
+        println!("Could not migrate briefing: {:?}", error);
+        continue;
+      }
+    };
+
+    let author_id = old
+      .author
+      .trim_start_matches("<@")
+      .trim_start_matches('!')
+      .trim_end_matches('>')
+      .parse::<u64>()
+      .unwrap_or(0);
+
+    let mut briefing = Briefing {
+      id: String::new(),
+      author: old.author,
+      author_id,
+      guild_id: default_guild,
+      heading: old.heading,
+      body: old.body,
+      category: None,
+      image: None,
+      image_message: None,
+      time: old.time,
+      approved: true,
+      revision: 0,
+      review_message: None,
+    };
+
+    add_briefing(con, &mut briefing, timestamp).await?;
+  }
+
+  con.del(&temp_key).await
+}
+
+fn render_entry(brief: &Briefing) -> String {
+  match brief.body {
+    Some(ref body) => format!(
+      concat!("**{}**\n", "```{}```", " - {} {}\n\n",),
+      brief.heading, body, brief.author, brief.time
+    ),
+    None => format!(
+      concat!("**{}**\n", " - {} {}\n\n"),
+      brief.heading, brief.author, brief.time
+    ),
+  }
+}
+
+/// Where a section goes in the digest: uncategorized first, then the
+/// suggested categories, then everything else
+fn section_rank(name: &str) -> usize {
+  if name == UNCATEGORIZED_SECTION {
+    return 0;
+  }
+
+  BRIEFING_CATEGORIES
+    .iter()
+    .position(|category| *category == name)
+    .map_or(BRIEFING_CATEGORIES.len() + 1, |position| position + 1)
+}
+
+/// Groups briefings by category, keeping them in the order they were sent
+fn briefing_sections(briefings: &[Briefing]) -> Vec<(&str, Vec<&Briefing>)> {
+  let mut sections: Vec<(&str, Vec<&Briefing>)> = vec![];
+
+  for briefing in briefings {
+    let name = briefing
+      .category
+      .as_deref()
+      .unwrap_or(UNCATEGORIZED_SECTION);
+
+    match sections.iter_mut().find(|(section, _)| *section == name) {
+      Some((_, entries)) => entries.push(briefing),
+      None => sections.push((name, vec![briefing])),
+    }
+  }
+
+  // This is stable, so made up categories stay in the order they first appeared
+  sections.sort_by_key(|(name, _)| section_rank(name));
+  sections
+}
+
+/// Lays out briefings as the messages (each a list of embeds) that get posted.
+/// It starts with a table of contents, with a field for each section, which
+/// goes on over more embeds if it gets too long for one. After that, each
+/// section gets its own embeds, which are split between entries rather than
+/// in the middle of one. The modal's length limits keep
+/// every entry under the description limit on its own
+fn briefing_digest(header: &str, briefings: &[Briefing]) -> Vec<Vec<DigestEmbed>> {
+  let sections = briefing_sections(briefings);
+
+  // The contents can take more than one embed, since an embed can only have
+  // so many fields and characters
+  let mut embeds = vec![];
+  let mut contents = DigestEmbed::new(header);
+
+  for (name, entries) in &sections {
+    let mut value = String::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+      let line = format!("- {}\n", entry.heading);
+      let more = format!("and {} more", entries.len() - index);
+
+      if value.chars().count() + line.chars().count() + more.chars().count()
+        > EMBED_FIELD_VALUE_LIMIT
+      {
+        value += &more;
+        break;
+      }
+
+      value += &line;
+    }
+
+    if contents.fields.len() == EMBED_FIELD_LIMIT
+      || contents.len() + name.chars().count() + value.chars().count() > MESSAGE_EMBED_TOTAL_LIMIT
+    {
+      embeds.push(contents);
+      contents = DigestEmbed::new(&format!("{}{}", header, CONTINUED_SUFFIX));
+    }
+
+    contents.fields.push((String::from(*name), value));
+  }
+
+  embeds.push(contents);
+
+  for (name, entries) in &sections {
+    let mut current = DigestEmbed::new(name);
+
+    for entry in entries {
+      let text = render_entry(entry);
+
+      if !current.description.is_empty()
+        && current.description.chars().count() + text.chars().count() > EMBED_DESCRIPTION_LIMIT
+      {
+        embeds.push(current);
+        current = DigestEmbed::new(&format!("{}{}", name, CONTINUED_SUFFIX));
+      }
+
+      current.description += &text;
+
+      // Embeds only have one image, so it ends the embed to stay under its entry
+      if let Some(image) = &entry.image {
+        current.image = Some(image.clone());
+        embeds.push(current);
+        current = DigestEmbed::new(&format!("{}{}", name, CONTINUED_SUFFIX));
+      }
+    }
+
+    if !current.description.is_empty() {
+      embeds.push(current);
+    }
+  }
+
+  let mut messages: Vec<Vec<DigestEmbed>> = vec![];
+  let mut current_message: Vec<DigestEmbed> = vec![];
+  let mut current_length = 0;
+
+  for embed in embeds {
+    let length = embed.len();
+
+    if !current_message.is_empty()
+      && (current_message.len() == MESSAGE_EMBED_LIMIT
+        || current_length + length > MESSAGE_EMBED_TOTAL_LIMIT)
+    {
+      messages.push(current_message);
+      current_message = vec![];
+      current_length = 0;
+    }
+
+    current_length += length;
+    current_message.push(embed);
+  }
+
+  messages.push(current_message);
+  messages
+}
+
+/// Sends an issue, which can take several messages. If one can't be sent, or
+/// `lease` is lost before it is, this gives how many went out before it along
+/// with the error
+pub async fn send_briefing(
+  briefings: &[Briefing],
+  config: &BriefingConfig,
+  issue: DateTime<Tz>,
+  http: &Arc<Http>,
+  lease: &Lease,
+) -> Result<(), (usize, String)> {
+  let channel = ChannelId(config.channel);
+  let header = config.header_for(issue);
+
+  let mut briefings = briefings.to_vec();
+  refresh_images(http, &mut briefings).await;
+
+  for (index, embeds) in briefing_digest(&header, &briefings).iter().enumerate() {
+    if !lease.is_leader() {
+      return Err((
+        index,
+        String::from("Another instance took over sending briefings"),
+      ));
+    }
+
+    if let Err(error) = channel
+      .send_message(http, |m| {
+        // Only ping once, rather than for every part of a long briefing
+        if let (0, Some(role)) = (index, config.ping_role) {
+          m.content(format!("<@&{}>", role));
+        }
+
+        m.set_embeds(embeds.iter().map(DigestEmbed::to_embed).collect())
+      })
+      .await
+    {
+      return Err((index, format!("Could not send briefing: {:?}", error)));
+    }
+  }
+
+  Ok(())
+}
+
+/// Sends the most recent issue of every guild's briefing that has not been
+/// sent yet
+pub async fn catch_up_briefings(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  http: &Arc<Http>,
+  lease: &Lease,
+  now: DateTime<Utc>,
+) -> Result<(), String> {
+  let configs: Vec<(u64, String)> = {
+    let mut redis_client = connection.lock().await;
+    redis_client
+      .0
+      .hgetall(BRIEFING_CONFIG_KEY)
+      .await
+      .map_err(|err| format!("Could not get briefing settings: {}", err))?
+  };
+
+  for (guild_id, config) in configs {
+    let config: BriefingConfig = match serde_json::from_str(&config) {
+      Ok(config) => config,
+      Err(error) => {
+        println!("Bad briefing settings for {}: {}", guild_id, error);
+        continue;
+      }
+    };
+
+    if let Err(error) = catch_up_briefing(connection, http, lease, guild_id, &config, now).await {
+      println!("Could not send briefing for {}: {}", guild_id, error);
+    }
+  }
+
+  Ok(())
+}
+
+/// Sends a guild's most recent issue if it has not been sent yet.
+/// The issue is claimed in Redis before sending, so it is never posted twice.
+/// If nothing could be sent, the claim is let go so it's tried again. If only
+/// part of it went out, the whole issue is archived anyway, since sending any
+/// of it again would post that part twice
+async fn catch_up_briefing(
+  connection: &Arc<Mutex<RedisWrapper>>,
+  http: &Arc<Http>,
+  lease: &Lease,
+  guild_id: u64,
+  config: &BriefingConfig,
+  now: DateTime<Utc>,
+) -> Result<(), String> {
+  let issue = config.latest_issue_time(now);
+  let issue_time = issue.timestamp();
+
+  let last: Option<i64> = {
+    let mut redis_client = connection.lock().await;
+    redis_client
+      .0
+      .hget(BRIEFING_SENT_KEY, guild_id)
+      .await
+      .map_err(|err| format!("Could not get last briefing: {}", err))?
+  };
+
+  if last.map_or(false, |last| last >= issue_time) {
+    return Ok(());
+  }
+
+  let claimed = {
+    let mut redis_client = connection.lock().await;
+    advance_field_checkpoint(&mut redis_client.0, BRIEFING_SENT_KEY, guild_id, issue_time)
+      .await
+      .map_err(|err| format!("Could not save briefing time: {}", err))?
+  };
+
+  if !claimed {
+    return Ok(());
+  }
+
+  // Anything not approved yet stays queued for the next issue
+  let briefings: Result<Vec<Briefing>, String> = {
+    let mut redis_client = connection.lock().await;
+    get_pending_briefings(&mut redis_client.0, issue_time)
+      .await
+      .map(|briefings| {
+        briefings
+          .into_iter()
+          .filter(|briefing| briefing.guild_id == guild_id && briefing.approved)
+          .collect()
+      })
+      .map_err(|err| format!("Could not get briefings: {}", err))
+  };
+
+  let result = match briefings {
+    Ok(briefings) if briefings.is_empty() => return Ok(()),
+    Ok(briefings) => match send_briefing(&briefings, config, issue, http, lease).await {
+      Ok(()) => Ok((briefings, None)),
+      // Sending any of it again would post the part that went out twice
+      Err((sent, error)) if sent > 0 => Ok((briefings, Some(error))),
+      Err((_, error)) => Err(error),
+    },
+    Err(error) => Err(error),
+  };
+
+  let (briefings, send_error) = match result {
+    Ok(sent) => sent,
+    Err(error) => {
+      let mut redis_client = connection.lock().await;
+
+      if let Err(release_error) = release_field_checkpoint(
+        &mut redis_client.0,
+        BRIEFING_SENT_KEY,
+        guild_id,
+        issue_time,
+        last,
+      )
+      .await
+      {
+        println!(
+          "Could not let go of briefing {}: {}",
+          issue_time, release_error
+        );
+      }
+
+      return Err(error);
+    }
+  };
+
+  let archived = ArchivedIssue {
+    time: issue_time,
+    header: config.header_for(issue),
+    briefings,
+  };
+
+  let mut redis_client = connection.lock().await;
+  archive_briefings(&mut redis_client.0, guild_id, &archived)
+    .await
+    .map_err(|err| format!("Could not archive sent briefings: {}", err))?;
+
+  match send_error {
+    Some(error) => Err(error),
+    None => Ok(()),
+  }
+}
+
+/// How many issues go in the feed
+const FEED_ISSUES: usize = 20;
+
+/// An issue of a briefing that has been sent, kept for `/briefing archive`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchivedIssue {
+  /// When it was sent (the timestamp of the issue)
+  pub time: i64,
+  pub header: String,
+  pub briefings: Vec<Briefing>,
+}
+
+/// Sent issues for a guild, by issue timestamp
+fn archive_key(guild_id: u64) -> String {
+  format!("safety:briefings:archive:{}", guild_id)
+}
+
+/// Moves sent briefings out of the queue and into the archive
+async fn archive_briefings(
+  con: &mut Connection,
+  guild_id: u64,
+  issue: &ArchivedIssue,
+) -> RedisResult<()> {
+  let ids: Vec<&String> = issue
+    .briefings
+    .iter()
+    .map(|briefing| &briefing.id)
+    .collect();
+  let serialized = serde_json::to_string(issue).expect("Issues are serializable");
+
+  pipe()
+    .atomic()
+    .hset(archive_key(guild_id), issue.time, serialized)
+    .ignore()
+    .zrem(BRIEFING_QUEUE_KEY, &ids)
+    .ignore()
+    .hdel(BRIEFING_ENTRIES_KEY, &ids)
+    .ignore()
+    .query_async(con)
+    .await
+}
+
+/// Gets every archived issue for a guild, newest first
+async fn get_archive(con: &mut Connection, guild_id: u64) -> RedisResult<Vec<ArchivedIssue>> {
+  let issues: Vec<String> = con.hvals(archive_key(guild_id)).await?;
+
+  let mut issues: Vec<ArchivedIssue> = issues
+    .iter()
+    .filter_map(|issue| serde_json::from_str(issue).ok())
+    .collect();
+
+  issues.sort_by_key(|issue| -issue.time);
+  Ok(issues)
+}
+
+/// Writes out a whole issue as Markdown, for issues too long to show in one message
+fn issue_markdown(issue: &ArchivedIssue) -> String {
+  let mut markdown = format!("# {}\n\n", issue.header);
+
+  for (name, entries) in briefing_sections(&issue.briefings) {
+    markdown += &format!("## {}\n\n", name);
+
+    for entry in entries {
+      markdown += &render_entry(entry);
+
+      if let Some(image) = &entry.image {
+        markdown += &format!("![]({})\n\n", image);
+      }
+    }
+  }
+
+  markdown
+}
+
+async fn archive_briefing(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Err(String::from("You can only use this in a server")),
+  };
+
+  let week = interaction.data.options[0]
+    .options
+    .iter()
+    .find(|option| option.name == "week")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str());
+
+  let (config, issues) = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+
+    let config = get_config(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Could not get briefing settings: {}", err))?;
+
+    let issues = get_archive(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| format!("Could not get old briefings: {}", err))?;
+
+    (config, issues)
+  };
+
+  let tz = match config {
+    Some(config) => config.timezone(),
+    None => return Err(String::from(NOT_SET_UP)),
+  };
+
+  if issues.is_empty() {
+    return Err(String::from("No briefings have been sent here yet"));
+  }
+
+  let issue_date = |issue: &ArchivedIssue| {
+    Utc
+      .timestamp_opt(issue.time, 0)
+      .single()
+      .unwrap_or_else(Utc::now)
+      .with_timezone(&tz)
+      .date_naive()
+  };
+
+  let week = match week {
+    Some(week) => match NaiveDate::parse_from_str(week.trim(), "%Y-%m-%d") {
+      Ok(date) => date.iso_week(),
+      Err(_) => {
+        return Err(format!(
+          "'{}' is not a date. Use year-month-day, like 2024-03-04",
+          week
+        ))
+      }
+    },
+    None => {
+      let mut content = String::from("Past briefings (use `week:` with one of these dates):\n");
+
+      for issue in issues.iter().take(25) {
+        content += &format!(
+          "\n`{}` {} ({} entries)",
+          issue_date(issue),
+          issue.header,
+          issue.briefings.len()
+        );
+      }
+
+      interaction
+        .create_followup_message(ctx, |msg| msg.content(content).ephemeral(true))
+        .await
+        .map_err(|err| format!("Could not respond: {}", err))?;
+
+      return Ok(());
+    }
+  };
+
+  let mut issue = match issues
+    .into_iter()
+    .find(|issue| issue_date(issue).iso_week() == week)
+  {
+    Some(issue) => issue,
+    None => {
+      return Err(String::from(
+        "No briefing went out that week. Use `/briefing archive` to see which weeks have one",
+      ))
+    }
+  };
+
+  refresh_images(&ctx.http, &mut issue.briefings).await;
+
+  let posts = briefing_digest(&issue.header, &issue.briefings);
+  let markdown = issue_markdown(&issue);
+  let filename = format!("briefing-{}.md", issue_date(&issue));
+
+  interaction
+    .create_followup_message(ctx, |msg| {
+      msg
+        .content(format!("The briefing from <t:{}:D>", issue.time))
+        .ephemeral(true)
+        .set_embeds(posts[0].iter().map(DigestEmbed::to_embed));
+
+      // The rest of a long issue doesn't fit, so send all of it as a file
+      if posts.len() > 1 {
+        msg.add_file((markdown.as_bytes(), filename.as_str()));
+      }
+
+      msg
+    })
+    .await
+    .map_err(|err| format!("Could not respond: {}", err))?;
+
+  Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+async fn author_name(ctx: &Context, guild_id: GuildId, briefing: &Briefing) -> String {
+  match ctx.cache.member(guild_id, briefing.author_id) {
+    Some(member) => member.display_name().to_string(),
+    None => match UserId(briefing.author_id).to_user(ctx).await {
+      Ok(user) => user.name,
+      Err(_) => String::from("Someone"),
+    },
+  }
+}
+
+/// Sends the most recent issues as an Atom feed, so they can be read in a
+/// feed reader
+async fn briefing_feed(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id,
+    None => return Err(String::from("You can only use this in a server")),
+  };
+
+  let mut issues = {
+    let lock = {
+      let mut context = ctx.data.write().await;
+      context
+        .get_mut::<RedisConnectionKey>()
+        .expect("Expected redis connection")
+        .clone()
+    };
+
+    let mut redis_client = lock.lock().await;
+    get_archive(&mut redis_client.0, guild_id.0)
+      .await
+      .map_err(|err| format!("Could not get old briefings: {}", err))?
+  };
+
+  for issue in issues.iter_mut().take(FEED_ISSUES) {
+    refresh_images(&ctx.http, &mut issue.briefings).await;
+  }
+
+  if issues.is_empty() {
+    return Err(String::from("No briefings have been sent here yet"));
+  }
+
+  let guild_name = guild_id.name(ctx).unwrap_or_else(|| String::from("Safety"));
+
+  let mut names: HashMap<u64, String> = HashMap::new();
+
+  for issue in issues.iter().take(FEED_ISSUES) {
+    for briefing in &issue.briefings {
+      if !names.contains_key(&briefing.author_id) {
+        let name = author_name(ctx, guild_id, briefing).await;
+        names.insert(briefing.author_id, name);
+      }
+    }
+  }
+
+  let timestamp = |time: i64| {
+    Utc
+      .timestamp_opt(time, 0)
+      .single()
+      .unwrap_or_else(Utc::now)
+      .to_rfc3339_opts(SecondsFormat::Secs, true)
+  };
+
+  let mut feed = format!(
+    concat!(
+      "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+      "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+      "  <title>{} briefings</title>\n",
+      "  <id>urn:safety-rust:briefings:{}</id>\n",
+      "  <updated>{}</updated>\n",
+      "  <author><name>Safety-chan</name></author>\n",
+    ),
+    escape_xml(&guild_name),
+    guild_id.0,
+    timestamp(issues[0].time)
+  );
+
+  for issue in issues.iter().take(FEED_ISSUES) {
+    let mut html = String::new();
+
+    for (name, entries) in briefing_sections(&issue.briefings) {
+      html += &format!("<h2>{}</h2>", escape_xml(name));
+
+      for entry in entries {
+        html += &format!("<h3>{}</h3>", escape_xml(&entry.heading));
+
+        if let Some(body) = &entry.body {
+          html += &format!("<p>{}</p>", escape_xml(body).replace('\n', "<br>"));
+        }
+
+        if let Some(image) = &entry.image {
+          html += &format!("<p><img src=\"{}\"></p>", escape_xml(image));
+        }
+
+        html += &format!(
+          "<p>- {} {}</p>",
+          escape_xml(
+            names
+              .get(&entry.author_id)
+              .map_or("Someone", String::as_str)
+          ),
+          escape_xml(&entry.time)
+        );
+      }
+    }
+
+    feed += &format!(
+      concat!(
+        "  <entry>\n",
+        "    <title>{}</title>\n",
+        "    <id>urn:safety-rust:briefings:{}:{}</id>\n",
+        "    <updated>{}</updated>\n",
+        "    <content type=\"html\">{}</content>\n",
+        "  </entry>\n",
+      ),
+      escape_xml(&issue.header),
+      guild_id.0,
+      issue.time,
+      timestamp(issue.time),
+      escape_xml(&html)
+    );
+  }
+
+  feed += "</feed>\n";
+
+  interaction
+    .create_followup_message(ctx, |msg| {
+      msg
+        .content("Here are the latest briefings. Open this file in a feed reader")
+        .add_file((feed.as_bytes(), "briefings.atom"))
+        .ephemeral(true)
+    })
+    .await
+    .map_err(|err| format!("Could not respond: {}", err))?;
 
   Ok(())
 }
@@ -0,0 +1,714 @@
+use crate::RedisConnectionKey;
+
+use rand::{seq::SliceRandom, thread_rng};
+use redis::{aio::Connection, cmd, pipe, AsyncCommands, RedisError};
+use serde::{Deserialize, Serialize};
+use serenity::{
+  builder::{CreateApplicationCommands, CreateEmbed},
+  model::{
+    application::{
+      command::*,
+      interaction::{application_command::*, *},
+    },
+    channel::{Embed, Message},
+    id::ChannelId,
+    Timestamp,
+  },
+  prelude::*,
+  utils::Colour,
+};
+
+use super::util::get_str_or_error;
+
+/// How many quotes `/quote search` lists
+const MAX_SEARCH_RESULTS: usize = 10;
+
+/// A message can have at most 10 embeds, one of which is the quote itself
+const MAX_QUOTED_EMBEDS: usize = 9;
+
+/// Embed descriptions can be at most 4096 characters, including the link
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+
+/// All the embeds in a message can have at most 6000 characters between them
+const MESSAGE_EMBED_TOTAL_LIMIT: usize = 6000;
+
+/// How many files a message can have
+const MAX_KEPT_ATTACHMENTS: usize = 10;
+
+/// How much the bot uploads in one message when keeping a quote's
+/// attachments, in bytes. Bots can upload 8 MB anywhere
+const MAX_KEPT_SIZE: u64 = 8 * 1024 * 1024;
+
+pub fn quote_command(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+  commands
+    .create_application_command(|command| command.name("quote").kind(CommandType::Message))
+    .create_application_command(|command| {
+      command
+        .name("quote")
+        .description("Read from this server's quote book (add to it by clicking on a message)")
+        .create_option(|op| {
+          op.name("random")
+            .kind(CommandOptionType::SubCommand)
+            .description("Get a random quote")
+        })
+        .create_option(|op| {
+          op.name("search")
+            .kind(CommandOptionType::SubCommand)
+            .description("Find quotes containing some text")
+            .create_sub_option(|text| {
+              text
+                .name("text")
+                .kind(CommandOptionType::String)
+                .description("What to look for")
+                .required(true)
+            })
+        })
+        .create_option(|op| {
+          op.name("by")
+            .kind(CommandOptionType::SubCommand)
+            .description("Get a random quote from someone")
+            .create_sub_option(|user| {
+              user
+                .name("user")
+                .kind(CommandOptionType::User)
+                .description("Whose quote to get")
+                .required(true)
+            })
+        })
+        .create_option(|op| {
+          op.name("delete")
+            .kind(CommandOptionType::SubCommand)
+            .description("(Manage Messages only) Remove a quote from the quote book")
+            .create_sub_option(|id| {
+              id.name("id")
+                .kind(CommandOptionType::Integer)
+                .description("The quote's number")
+                .min_int_value(1)
+                .required(true)
+            })
+        })
+    })
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct QuotedAttachment {
+  filename: String,
+  url: String,
+  image: bool,
+  /// Which file on the quote's `attachment_message` is a copy of this one
+  #[serde(default)]
+  kept: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Quote {
+  id: u64,
+  content: String,
+  author_id: u64,
+  /// Their name when quoted, in case they leave
+  author: String,
+  link: String,
+  /// When the message was sent, as a UNIX timestamp
+  time: i64,
+  quoted_by: u64,
+  attachments: Vec<QuotedAttachment>,
+  /// Where the bot uploaded the attachments again, as (channel, message).
+  /// Discord's attachment links expire, so they're refreshed from this
+  /// before they're shown
+  #[serde(default)]
+  attachment_message: Option<(u64, u64)>,
+  embeds: Vec<Embed>,
+}
+
+impl Quote {
+  fn new(id: u64, guild_id: u64, message: &Message, quoted_by: u64) -> Quote {
+    Quote {
+      id,
+      content: message.content.clone(),
+      author_id: message.author.id.0,
+      author: message.author.name.clone(),
+      link: format!(
+        "https://discord.com/channels/{}/{}/{}",
+        guild_id, message.channel_id.0, message.id.0
+      ),
+      time: message.timestamp.unix_timestamp(),
+      quoted_by,
+      attachments: message
+        .attachments
+        .iter()
+        .map(|attachment| QuotedAttachment {
+          filename: attachment.filename.clone(),
+          url: attachment.url.clone(),
+          image: attachment.width.is_some(),
+          kept: None,
+        })
+        .collect(),
+      attachment_message: None,
+      embeds: message.embeds.clone(),
+    }
+  }
+
+  /// The quote, followed by any embeds the message had
+  fn to_embeds(&self) -> Vec<CreateEmbed> {
+    let mut embed = CreateEmbed::default();
+
+    let jump = format!("[Jump to message]({})", self.link);
+    // What's left after the link and the blank line before it
+    let room = EMBED_DESCRIPTION_LIMIT - jump.chars().count() - 2;
+
+    let content = if self.content.chars().count() > room {
+      format!(
+        "{}…",
+        self.content.chars().take(room - 1).collect::<String>()
+      )
+    } else {
+      self.content.clone()
+    };
+
+    let description = format!("{}\n\n{}", content, jump);
+    let number = format!("Quote #{}", self.id);
+
+    // Counted to keep the quoted embeds under the limit for all of them
+    let mut length =
+      self.author.chars().count() + description.chars().count() + number.chars().count();
+
+    embed
+      .author(|author| author.name(&self.author))
+      .description(description)
+      .colour(Colour::GOLD)
+      .footer(|footer| footer.text(number));
+
+    if let Ok(time) = Timestamp::from_unix_timestamp(self.time) {
+      embed.timestamp(time);
+    }
+
+    if let Some(image) = self.attachments.iter().find(|attachment| attachment.image) {
+      embed.image(&image.url);
+    }
+
+    let files: Vec<String> = self
+      .attachments
+      .iter()
+      .map(|attachment| format!("[{}]({})", attachment.filename, attachment.url))
+      .collect();
+
+    if !files.is_empty() {
+      let mut value = String::new();
+
+      // Fields can be at most 1024 characters
+      for file in files {
+        if value.len() + file.len() + 1 > 1024 {
+          break;
+        }
+
+        value += &file;
+        value += "\n";
+      }
+
+      length += "Attachments".len() + value.chars().count();
+      embed.field("Attachments", value, false);
+    }
+
+    let mut embeds = vec![embed];
+
+    // Any that don't fit are left off
+    for quoted in self.embeds.iter().take(MAX_QUOTED_EMBEDS) {
+      length += embed_length(quoted);
+
+      if length > MESSAGE_EMBED_TOTAL_LIMIT {
+        break;
+      }
+
+      embeds.push(CreateEmbed::from(quoted.clone()));
+    }
+
+    embeds
+  }
+}
+
+/// The characters in an embed that count towards the limit for a message
+fn embed_length(embed: &Embed) -> usize {
+  let text = |text: &Option<String>| text.as_ref().map_or(0, |text| text.chars().count());
+
+  text(&embed.title)
+    + text(&embed.description)
+    + embed
+      .author
+      .as_ref()
+      .map_or(0, |author| author.name.chars().count())
+    + embed
+      .footer
+      .as_ref()
+      .map_or(0, |footer| footer.text.chars().count())
+    + embed
+      .fields
+      .iter()
+      .map(|field| field.name.chars().count() + field.value.chars().count())
+      .sum::<usize>()
+}
+
+/// Gets fresh links for attachments the bot uploaded again, since the old ones
+/// expire. If the upload is gone, the last links are kept
+async fn refresh_attachments(ctx: &Context, quote: &mut Quote) {
+  let (channel_id, message_id) = match quote.attachment_message {
+    Some(kept) => kept,
+    None => return,
+  };
+
+  if let Ok(message) = ChannelId(channel_id).message(ctx, message_id).await {
+    for attachment in &mut quote.attachments {
+      if let Some(kept) = attachment
+        .kept
+        .and_then(|kept| message.attachments.get(kept))
+      {
+        attachment.url = kept.url.clone();
+      }
+    }
+  }
+}
+
+fn quotes_key(guild_id: u64) -> String {
+  format!("quotes:{}", guild_id)
+}
+
+fn quote_ids_key(guild_id: u64) -> String {
+  format!("quotes:{}:ids", guild_id)
+}
+
+/// Which quote each message is, so it isn't quoted twice
+fn quoted_messages_key(guild_id: u64) -> String {
+  format!("quotes:{}:messages", guild_id)
+}
+
+fn parse_quote(serialized: &str) -> Result<Quote, String> {
+  serde_json::from_str(serialized).map_err(|err| format!("Could not read quote: {}", err))
+}
+
+async fn get_quote(conn: &mut Connection, guild_id: u64, id: u64) -> Result<Option<Quote>, String> {
+  let serialized: Option<String> = conn
+    .hget(quotes_key(guild_id), id)
+    .await
+    .map_err(|err| format!("Could not get quote: {}", err))?;
+
+  serialized
+    .map(|serialized| parse_quote(&serialized))
+    .transpose()
+}
+
+async fn get_quotes(conn: &mut Connection, guild_id: u64) -> Result<Vec<Quote>, String> {
+  let serialized: Vec<String> = conn
+    .hvals(quotes_key(guild_id))
+    .await
+    .map_err(|err| format!("Could not get quotes: {}", err))?;
+
+  let mut quotes = serialized
+    .iter()
+    .map(|serialized| parse_quote(serialized))
+    .collect::<Result<Vec<Quote>, String>>()?;
+
+  quotes.sort_by_key(|quote| quote.id);
+  Ok(quotes)
+}
+
+/// Adds a message to the quote book from the context menu
+async fn add_quote(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  guild_id: u64,
+) -> Result<(), String> {
+  let message = match interaction.data.resolved.messages.values().next() {
+    Some(message) => message,
+    None => return Err(String::from("Missing message")),
+  };
+
+  if message.content.is_empty() && message.attachments.is_empty() && message.embeds.is_empty() {
+    return Err(String::from("There's nothing in that message to quote"));
+  }
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let quote = {
+    let mut redis_client = lock.lock().await;
+    let conn = &mut redis_client.0;
+
+    let existing: Option<u64> = conn
+      .hget(quoted_messages_key(guild_id), message.id.0)
+      .await
+      .map_err(|err| format!("Could not save quote: {}", err))?;
+
+    if let Some(id) = existing {
+      return Err(format!("That message is already quote #{}", id));
+    }
+
+    let id: u64 = conn
+      .incr(quote_ids_key(guild_id), 1)
+      .await
+      .map_err(|err| format!("Could not save quote: {}", err))?;
+
+    let quote = Quote::new(id, guild_id, message, interaction.user.id.0);
+    let serialized = serde_json::to_string(&quote).expect("Quotes are serializable");
+
+    let result: Result<(), RedisError> = pipe()
+      .atomic()
+      .hset(quotes_key(guild_id), id, serialized)
+      .ignore()
+      .hset(quoted_messages_key(guild_id), message.id.0, id)
+      .ignore()
+      .query_async(conn)
+      .await;
+
+    if let Err(error) = result {
+      return Err(format!("Could not save quote: {}", error));
+    }
+
+    quote
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content(format!(
+              "{} added quote #{} to the quote book",
+              interaction.user.mention(),
+              quote.id
+            ))
+            .set_embeds(quote.to_embeds())
+        })
+    })
+    .await;
+
+  // The quote is already saved with the original links, so it's still there
+  // (for a while) if this doesn't work
+  if let Err(error) = keep_attachments(ctx, interaction, guild_id, message, quote.id).await {
+    println!(
+      "Could not keep attachments for quote #{}: {}",
+      quote.id, error
+    );
+  }
+
+  Ok(())
+}
+
+/// Uploads a quoted message's files again, in a follow-up the bot owns, so
+/// the quote still has them after the original links expire or the message
+/// is deleted
+async fn keep_attachments(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  guild_id: u64,
+  message: &Message,
+  id: u64,
+) -> Result<(), String> {
+  let mut files: Vec<(Vec<u8>, &str)> = vec![];
+  let mut kept: Vec<usize> = vec![];
+  let mut size = 0;
+
+  // Any that don't fit keep their original link
+  for (index, attachment) in message.attachments.iter().enumerate() {
+    if files.len() == MAX_KEPT_ATTACHMENTS || size + attachment.size > MAX_KEPT_SIZE {
+      continue;
+    }
+
+    let bytes = attachment
+      .download()
+      .await
+      .map_err(|err| format!("Could not download {}: {}", attachment.filename, err))?;
+
+    size += attachment.size;
+    files.push((bytes, &attachment.filename));
+    kept.push(index);
+  }
+
+  if files.is_empty() {
+    return Ok(());
+  }
+
+  let copy = interaction
+    .create_followup_message(ctx, |msg| {
+      msg.content(format!("Attachments from quote #{}", id));
+
+      for (bytes, filename) in &files {
+        msg.add_file((bytes.as_slice(), *filename));
+      }
+
+      msg
+    })
+    .await
+    .map_err(|err| format!("Could not upload attachments: {}", err))?;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let mut redis_client = lock.lock().await;
+
+  // It may have been deleted in the meantime
+  let mut quote = match get_quote(&mut redis_client.0, guild_id, id).await? {
+    Some(quote) => quote,
+    None => return Ok(()),
+  };
+
+  for (copied, index) in kept.into_iter().enumerate() {
+    if let (Some(attachment), Some(uploaded)) = (
+      quote.attachments.get_mut(index),
+      copy.attachments.get(copied),
+    ) {
+      attachment.url = uploaded.url.clone();
+      attachment.kept = Some(copied);
+    }
+  }
+
+  quote.attachment_message = Some((copy.channel_id.0, copy.id.0));
+
+  let serialized = serde_json::to_string(&quote).expect("Quotes are serializable");
+
+  let result: Result<(), RedisError> = redis_client
+    .0
+    .hset(quotes_key(guild_id), id, serialized)
+    .await;
+
+  result.map_err(|err| format!("Could not save quote: {}", err))
+}
+
+fn search_result(quote: &Quote) -> String {
+  let first_line = quote.content.lines().next().unwrap_or_default();
+
+  let snippet: String = if first_line.chars().count() > 80 {
+    format!("{}…", first_line.chars().take(79).collect::<String>())
+  } else if first_line.is_empty() {
+    String::from("(attachment)")
+  } else {
+    String::from(first_line)
+  };
+
+  format!(
+    "`#{}` **{}**: {} ([jump]({}))",
+    quote.id, quote.author, snippet, quote.link
+  )
+}
+
+pub async fn interaction_quote(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Err(String::from("Quotes only work in servers")),
+  };
+
+  if interaction.data.kind == CommandType::Message {
+    return add_quote(ctx, interaction, guild_id).await;
+  }
+
+  let subcommand = match interaction.data.options.get(0) {
+    Some(subcommand) => subcommand,
+    None => return Err(String::from("Must have subcommand")),
+  };
+
+  let option = |name: &str| {
+    subcommand
+      .options
+      .iter()
+      .find(|option| option.name == name)
+      .and_then(|option| option.value.clone())
+  };
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let mut redis_client = lock.lock().await;
+  let conn = &mut redis_client.0;
+
+  // The copy of a deleted quote's attachments, which is removed after letting
+  // go of Redis
+  let mut deleted_copy: Option<(u64, u64)> = None;
+
+  // A quote to show is turned into embeds after letting go of Redis, since
+  // its attachment links are refreshed first
+  let (content, shown, mut embeds, ephemeral) = match subcommand.name.as_str() {
+    "random" => {
+      let id: Option<u64> = cmd("HRANDFIELD")
+        .arg(quotes_key(guild_id))
+        .query_async(conn)
+        .await
+        .map_err(|err| format!("Could not get quote: {}", err))?;
+
+      let quote = match id {
+        Some(id) => get_quote(conn, guild_id, id).await?,
+        None => None,
+      };
+
+      match quote {
+        Some(quote) => (String::new(), Some(quote), vec![], false),
+        None => {
+          return Err(String::from(
+            "The quote book is empty. Add to it by clicking on a message, then Apps, then quote",
+          ))
+        }
+      }
+    }
+    "search" => {
+      let text = get_str_or_error(&option("text"), "You must give some text to search for")?;
+      let text = text.trim().to_lowercase();
+
+      let found: Vec<Quote> = get_quotes(conn, guild_id)
+        .await?
+        .into_iter()
+        .filter(|quote| quote.content.to_lowercase().contains(&text))
+        .collect();
+
+      match found.len() {
+        0 => return Err(format!("No quotes contain \"{}\"", text)),
+        1 => (String::new(), found.into_iter().next(), vec![], false),
+        count => {
+          let mut embed = CreateEmbed::default();
+
+          embed
+            .title(format!("Quotes containing \"{}\"", text))
+            .description(
+              found
+                .iter()
+                .rev()
+                .take(MAX_SEARCH_RESULTS)
+                .map(search_result)
+                .collect::<Vec<String>>()
+                .join("\n"),
+            )
+            .colour(Colour::GOLD);
+
+          if count > MAX_SEARCH_RESULTS {
+            embed.footer(|footer| {
+              footer.text(format!(
+                "Showing the newest {} of {} quotes",
+                MAX_SEARCH_RESULTS, count
+              ))
+            });
+          }
+
+          (String::new(), None, vec![embed], false)
+        }
+      }
+    }
+    "by" => {
+      let user_id = get_str_or_error(&option("user"), "You must pick someone")?
+        .parse::<u64>()
+        .map_err(|_| String::from("Invalid user"))?;
+
+      let theirs: Vec<Quote> = get_quotes(conn, guild_id)
+        .await?
+        .into_iter()
+        .filter(|quote| quote.author_id == user_id)
+        .collect();
+
+      match theirs.choose(&mut thread_rng()) {
+        Some(quote) => (
+          format!("One of <@{}>'s {} quotes", user_id, theirs.len()),
+          Some(quote.clone()),
+          vec![],
+          false,
+        ),
+        None => return Err(String::from("They haven't been quoted yet")),
+      }
+    }
+    "delete" => {
+      let is_moderator = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_messages());
+
+      if !is_moderator {
+        return Err(String::from(
+          "You need the Manage Messages permission to delete quotes",
+        ));
+      }
+
+      let id = option("id")
+        .and_then(|id| id.as_u64())
+        .ok_or_else(|| String::from("You must give the quote's number"))?;
+
+      let quote = match get_quote(conn, guild_id, id).await? {
+        Some(quote) => quote,
+        None => return Err(format!("There is no quote #{}", id)),
+      };
+
+      let message_id = quote.link.rsplit('/').next().unwrap_or_default();
+
+      let result: Result<(), RedisError> = pipe()
+        .atomic()
+        .hdel(quotes_key(guild_id), id)
+        .ignore()
+        .hdel(quoted_messages_key(guild_id), message_id)
+        .ignore()
+        .query_async(conn)
+        .await;
+
+      if let Err(error) = result {
+        return Err(format!("Could not delete quote: {}", error));
+      }
+
+      deleted_copy = quote.attachment_message;
+
+      (
+        format!("Deleted quote #{} by {}", id, quote.author),
+        None,
+        vec![],
+        true,
+      )
+    }
+    _ => return Err(String::from("Unexpected command")),
+  };
+
+  drop(redis_client);
+
+  if let Some((channel_id, message_id)) = deleted_copy {
+    ChannelId(channel_id)
+      .delete_message(ctx, message_id)
+      .await
+      .map_err(|err| {
+        format!(
+          "Deleted the quote, but could not delete the copy of its attachments: {}",
+          err
+        )
+      })?;
+  }
+
+  if let Some(mut quote) = shown {
+    refresh_attachments(ctx, &mut quote).await;
+    embeds = quote.to_embeds();
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |resp| {
+      resp
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content(content)
+            .set_embeds(embeds)
+            .allowed_mentions(|mentions| mentions.empty_users())
+            .ephemeral(ephemeral)
+        })
+    })
+    .await;
+
+  Ok(())
+}
@@ -1,34 +1,229 @@
+use crate::RedisConnectionKey;
+
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::{Hash, Hasher};
 
-use lazy_static::lazy_static;
-use rand::{thread_rng, Rng};
-use regex::Regex;
+use chrono::{Duration, TimeZone, Utc};
+
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use redis::{aio::Connection, pipe, AsyncCommands, RedisError};
+use serde::{Deserialize, Serialize};
 use serenity::model::prelude::interaction::InteractionResponseType;
 use serenity::{
   builder::CreateApplicationCommands,
-  model::application::{command::*, interaction::application_command::*},
+  model::application::{
+    command::*,
+    interaction::{application_command::*, autocomplete::AutocompleteInteraction},
+  },
   prelude::*,
 };
+use sha2::{Digest, Sha256};
+
+use super::util::{get_mention, get_str_or_error};
+use crate::util::dice::{parse, Distribution, Roll};
+
+/// Macro names can be at most this long
+const MAX_MACRO_NAME_LENGTH: u16 = 32;
+
+/// How many macros someone can have in each scope (everywhere, or one server).
+/// This is also as many as autocomplete can show
+const MAX_MACROS: usize = 25;
+
+/// Seeds for verifiable rolls (which are secret until revealed), by their hash
+const SEEDS_KEY: &str = "rolls:seeds";
+
+/// The hash of the seed each guild (or channel, outside of guilds) is using
+const CURRENT_SEEDS_KEY: &str = "rolls:seeds:current";
+
+/// How long verifiable rolls are kept, so they can be checked
+const VERIFIABLE_ROLL_SECONDS: usize = 90 * 24 * 60 * 60;
+
+/// How many rolls each channel's history keeps
+const MAX_HISTORY: isize = 1000;
+
+/// The most rolls `/roll history` can show
+const MAX_HISTORY_SHOWN: usize = 25;
+
+/// How far back `/roll export` can go, in hours
+const MAX_EXPORT_HOURS: usize = 24 * 30;
 
-use super::util::get_mention;
+/// The most bars `/roll stats` shows. Totals are grouped to fit
+const MAX_HISTOGRAM_BARS: i64 = 30;
+
+/// How wide the longest bar is
+const HISTOGRAM_WIDTH: f64 = 25.0;
+
+/// Totals rarer than this are left off the ends of the histogram
+const HISTOGRAM_CUTOFF: f64 = 0.0005;
 
 pub fn roll_command(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
   commands.create_application_command(|command| {
-    let mut comm = command
+    command
       .name("roll")
-      .description("Roll one or more sets of dice");
-
-    for idx in 1..=20 {
-      comm = comm.create_option(|op| {
-        op.name(format!("die-{}", idx))
-          .kind(CommandOptionType::String)
-          .description("A die roll like 2d20+5 or 4d4dldh2+1 (4d4, drop low, 2 highest + 1)")
-          .required(idx == 1)
-      })
-    }
+      .description("Roll one or more sets of dice")
+      .create_option(|op| {
+        op.name("dice")
+          .kind(CommandOptionType::SubCommand)
+          .description("Roll one or more sets of dice");
+
+        for idx in 1..=20 {
+          op.create_sub_option(|die| {
+            die
+              .name(format!("die-{}", idx))
+              .kind(CommandOptionType::String)
+              .description(
+                "A roll like 2d20+5, (1d8+2)*2, 4d6kh3, adv+5, 4d6!, 2d6ro1, 10d10>=7 or 4dF",
+              )
+              .required(idx == 1)
+          });
+        }
 
-    comm
+        op.create_sub_option(|verifiable| {
+          verifiable
+            .name("verifiable")
+            .kind(CommandOptionType::Boolean)
+            .description("Roll with a seed that is revealed later, so anyone can check it")
+            .required(false)
+        })
+      })
+      .create_option(|op| {
+        op.name("seed")
+          .kind(CommandOptionType::SubCommand)
+          .description("See the hash of the seed for verifiable rolls here, before you roll")
+      })
+      .create_option(|op| {
+        op.name("verify")
+          .kind(CommandOptionType::SubCommand)
+          .description("Reveal the seed of a verifiable roll, and roll it again to check it")
+          .create_sub_option(|id| {
+            id.name("id")
+              .kind(CommandOptionType::String)
+              .description("The roll's ID, shown under it")
+              .required(true)
+          })
+      })
+      .create_option(|op| {
+        op.name("history")
+          .kind(CommandOptionType::SubCommand)
+          .description("See the latest rolls in this channel")
+          .create_sub_option(|user| {
+            user
+              .name("user")
+              .kind(CommandOptionType::User)
+              .description("Only show this person's rolls")
+              .required(false)
+          })
+          .create_sub_option(|limit| {
+            limit
+              .name("limit")
+              .kind(CommandOptionType::Integer)
+              .description("How many rolls to show (default 10)")
+              .min_int_value(1)
+              .max_int_value(MAX_HISTORY_SHOWN)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("export")
+          .kind(CommandOptionType::SubCommand)
+          .description(
+            "Get this channel's recent rolls, and a summary for each player, as CSV files",
+          )
+          .create_sub_option(|hours| {
+            hours
+              .name("hours")
+              .kind(CommandOptionType::Integer)
+              .description("How many hours back the session goes (default 24)")
+              .min_int_value(1)
+              .max_int_value(MAX_EXPORT_HOURS)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("stats")
+          .kind(CommandOptionType::SubCommand)
+          .description("See the odds of a roll, without rolling it")
+          .create_sub_option(|expr| {
+            expr
+              .name("expr")
+              .kind(CommandOptionType::String)
+              .description("The roll, like 4d6kh3")
+              .required(true)
+          })
+          .create_sub_option(|target| {
+            target
+              .name("target")
+              .kind(CommandOptionType::Integer)
+              .description("Also see the chance of rolling at least this")
+              .required(false)
+          })
+      })
+      .create_option(|group| {
+        group
+          .name("macro")
+          .kind(CommandOptionType::SubCommandGroup)
+          .description("Save rolls you make a lot, and roll them by name")
+          .create_sub_option(|op| {
+            op.name("save")
+              .kind(CommandOptionType::SubCommand)
+              .description("Save a roll as a macro (replacing any with the same name)")
+              .create_sub_option(|name| {
+                name
+                  .name("name")
+                  .kind(CommandOptionType::String)
+                  .description("What to call it, like attack")
+                  .max_length(MAX_MACRO_NAME_LENGTH)
+                  .required(true)
+              })
+              .create_sub_option(|expr| {
+                expr
+                  .name("expr")
+                  .kind(CommandOptionType::String)
+                  .description("The roll, like 1d20+7")
+                  .required(true)
+              })
+              .create_sub_option(|server| {
+                server
+                  .name("server")
+                  .kind(CommandOptionType::Boolean)
+                  .description("Only use it in this server (otherwise it works everywhere)")
+                  .required(false)
+              })
+          })
+          .create_sub_option(|op| {
+            op.name("run")
+              .kind(CommandOptionType::SubCommand)
+              .description("Roll one of your macros")
+              .create_sub_option(|name| {
+                name
+                  .name("name")
+                  .kind(CommandOptionType::String)
+                  .description("The macro to roll")
+                  .set_autocomplete(true)
+                  .required(true)
+              })
+          })
+          .create_sub_option(|op| {
+            op.name("list")
+              .kind(CommandOptionType::SubCommand)
+              .description("See your macros")
+          })
+          .create_sub_option(|op| {
+            op.name("delete")
+              .kind(CommandOptionType::SubCommand)
+              .description("Delete one of your macros (this server's first, if you have both)")
+              .create_sub_option(|name| {
+                name
+                  .name("name")
+                  .kind(CommandOptionType::String)
+                  .description("The macro to delete")
+                  .set_autocomplete(true)
+                  .required(true)
+              })
+          })
+      })
   })
 }
 
@@ -36,210 +231,1132 @@ pub async fn interaction_roll(
   ctx: &Context,
   interaction: &ApplicationCommandInteraction,
 ) -> Result<(), String> {
-  let data = &interaction.data;
+  let options = &interaction.data.options;
 
-  let mut total_string = String::from(">>> ");
+  if options.is_empty() {
+    return Err(String::from("Must have subcommand"));
+  }
 
-  let mut total_sum: i32 = 0;
-
-  for option in &data.options {
-    if let Some(die) = &option.value {
-      if let Some(die_str) = die.as_str() {
-        match handle_roll(&die_str) {
-          Ok((count, message)) => {
-            total_sum += count;
-            total_string += &message;
-          }
-          Err(error) => return Err(error.to_string()),
-        };
+  match options[0].name.as_str() {
+    "dice" => {
+      let rolls: Vec<&str> = options[0]
+        .options
+        .iter()
+        .filter(|option| option.name.starts_with("die-"))
+        .filter_map(|option| option.value.as_ref())
+        .filter_map(|value| value.as_str())
+        .collect();
+
+      let verifiable = options[0]
+        .options
+        .iter()
+        .find(|option| option.name == "verifiable")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+        == Some(true);
+
+      let mention = get_mention(interaction);
+      let intro = format!("{}, you rolled", mention);
+
+      if verifiable {
+        return verifiable_roll(ctx, interaction, &intro, &rolls).await;
       }
+
+      let rolled = roll_message(&intro, &rolls, 0, &mut thread_rng())?;
+      log_rolls(ctx, interaction, &rolled.rolls).await;
+
+      send_roll(ctx, interaction, rolled.message).await
     }
-  }
+    "seed" => {
+      let lock = {
+        let mut context = ctx.data.write().await;
+        context
+          .get_mut::<RedisConnectionKey>()
+          .expect("Expected redis connection")
+          .clone()
+      };
 
-  let mention = get_mention(&interaction);
+      let (seed_hash, _) = {
+        let mut redis_client = lock.lock().await;
+        current_seed(&mut redis_client.0, seed_scope(interaction))
+          .await
+          .map_err(|err| err.to_string())?
+      };
 
-  let final_msg = format!(
-    "{}, you rolled a total of **{}**\n{}\n",
-    mention, total_sum, total_string
-  );
+      let message = format!(
+        "Verifiable rolls here use a seed with the SHA-256 hash `{}`. The seed is revealed (and replaced) the first time one of them is checked with `/roll verify`",
+        seed_hash
+      );
+
+      send_roll(ctx, interaction, message).await
+    }
+    "verify" => verify_roll(ctx, interaction).await,
+    "history" => roll_history(ctx, interaction).await,
+    "export" => export_rolls(ctx, interaction).await,
+    "stats" => {
+      let expr = get_str_or_error(
+        &options[0]
+          .options
+          .iter()
+          .find(|option| option.name == "expr")
+          .and_then(|option| option.value.clone()),
+        "You must give a roll",
+      )?;
+
+      let target = options[0]
+        .options
+        .iter()
+        .find(|option| option.name == "target")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_i64());
 
+      let message = roll_stats(&expr, target)?;
+      send_roll(ctx, interaction, message).await
+    }
+    "macro" => interaction_roll_macro(ctx, interaction).await,
+    _ => Err(String::from("Unexpected command")),
+  }
+}
+
+async fn send_roll(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  message: String,
+) -> Result<(), String> {
   let _ = interaction
     .create_interaction_response(ctx, |response| {
       response
         .kind(InteractionResponseType::ChannelMessageWithSource)
-        .interaction_response_data(|message| message.content(final_msg))
+        .interaction_response_data(|msg| msg.content(message))
     })
     .await;
 
   Ok(())
 }
 
-fn handle_roll(roll_str: &str) -> Result<(i32, String), String> {
-  lazy_static! {
-    static ref RE: Regex = Regex::new(
-      r"(?x)
-      (?P<count>\d+)  # number of times to roll this die
-      d(?P<size>\d+)  # number of sides (nonzero)
-      ((?P<addition>[+-]\d+))?  # modifier for the overall roll
-      (dl(?P<low>\d*))?         # how many high rolls to drop
-      (dh(?P<high>\d*))?        # how many lows to drop"
-    )
-    .unwrap();
+/// What `roll_message` rolled
+struct RolledMessage {
+  total: i64,
+  /// Each roll (as it was understood), and how it went
+  rolls: Vec<(String, Roll)>,
+  message: String,
+}
+
+/// Rolls each of `rolls`, and creates a message with their total and each
+/// of the dice
+/// # Arguments
+/// - `intro` - what comes before the total, like "@someone, you rolled"
+/// - `rolls` - the rolls, like 1d20+7
+/// - `reserved` - how many characters will be added to the message
+/// - `rng` - where the random numbers come from
+fn roll_message<R: Rng>(
+  intro: &str,
+  rolls: &[&str],
+  reserved: usize,
+  rng: &mut R,
+) -> Result<RolledMessage, String> {
+  let mut total_string = String::from(">>> ");
+
+  let mut total_sum: i64 = 0;
+
+  let mut rolled: Vec<(String, Roll)> = vec![];
+
+  for roll_str in rolls {
+    let (expr, roll) = handle_roll(roll_str, rng)?;
+    total_sum = total_sum.saturating_add(roll.total);
+    total_string += &format!("`{}` = **{}**\n{}\n", expr, roll.total, roll.breakdown);
+    rolled.push((expr, roll));
   }
 
-  let caps = match RE.captures(roll_str) {
-    Some(captures) => captures,
-    None => return error_hash(roll_str, "Not a valid die roll"),
+  let final_msg = format!("{} a total of **{}**\n{}\n", intro, total_sum, total_string);
+
+  // Discord messages can be at most 2000 characters
+  let message = if final_msg.chars().count() + reserved > 2000 {
+    format!(
+      "{} a total of **{}**\n(That's too many dice to show them all)",
+      intro, total_sum
+    )
+  } else {
+    final_msg
+  };
+
+  Ok(RolledMessage {
+    total: total_sum,
+    rolls: rolled,
+    message,
+  })
+}
+
+/// Rolls `roll_str`, returning it (as it was understood) and how it went
+fn handle_roll<R: Rng>(roll_str: &str, rng: &mut R) -> Result<(String, Roll), String> {
+  let expr = match parse(roll_str) {
+    Ok(expr) => expr,
+    Err(error) if error.guess => return error_hash(roll_str, &error.render(roll_str)),
+    Err(error) => return Err(error.render(roll_str)),
   };
 
-  let size = match caps.name("size").unwrap().as_str().parse::<u32>() {
-    Ok(int) => int,
-    Err(_) => return error_hash(roll_str, "Must provide a valid, nonnegative size"),
+  let roll = match expr.roll(rng) {
+    Ok(roll) => roll,
+    Err(error) => return error_hash(roll_str, &format!("**{}**.", error)),
   };
 
-  if size == 0 {
-    return error_hash(roll_str, "I will not roll a d0");
+  Ok((expr.to_string(), roll))
+}
+
+/// Hashes and rolls an invalid die roll and returns the error message.
+/// This will always return an error
+/// # Arguments
+/// - die:    a string representing an invalid die roll, to be hashed
+/// - error:  the (already formatted) error message corresponding to the roll
+fn error_hash<T>(die: &str, error: &str) -> Result<T, String> {
+  let mut s = DefaultHasher::new();
+  die.hash(&mut s);
+
+  let hashed_val = (s.finish() % 1000) as u32;
+
+  let rng = thread_rng().gen_range(1..=hashed_val);
+
+  Err(format!(
+    "{}\nBut here's a guess for {} = 1d{}: **{}**",
+    error, die, hashed_val, rng
+  ))
+}
+
+/// A saved roll
+struct RollMacro {
+  name: String,
+  expr: String,
+  /// Whether it's only for this server
+  server: bool,
+}
+
+/// A roll made with a seed, kept so it can be checked with `/roll verify`
+#[derive(Deserialize, Serialize)]
+struct VerifiableRoll {
+  /// The SHA-256 hash of the seed it was rolled with
+  seed_hash: String,
+  /// The guild (or channel) whose seed it was rolled with
+  scope: u64,
+  user_id: u64,
+  rolls: Vec<String>,
+  total: i64,
+}
+
+fn verifiable_roll_key(interaction_id: u64) -> String {
+  format!("rolls:verifiable:{}", interaction_id)
+}
+
+/// Seeds are shared by a guild, or a channel outside of guilds
+fn seed_scope(interaction: &ApplicationCommandInteraction) -> u64 {
+  interaction
+    .guild_id
+    .map_or(interaction.channel_id.0, |guild| guild.0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn seed_hash(seed: &str) -> String {
+  to_hex(&Sha256::digest(seed.as_bytes()))
+}
+
+/// The random numbers for a verifiable roll: ChaCha20, seeded with the
+/// SHA-256 hash of `{seed}:{interaction ID}`
+fn verifiable_rng(seed: &str, interaction_id: u64) -> ChaCha20Rng {
+  let digest = Sha256::digest(format!("{}:{}", seed, interaction_id).as_bytes());
+  ChaCha20Rng::from_seed(digest.into())
+}
+
+/// Makes a new seed for `scope`, replacing the current one (if any).
+/// Returns its hash and the seed
+async fn new_seed(conn: &mut Connection, scope: u64) -> Result<(String, String), RedisError> {
+  let seed = to_hex(&thread_rng().gen::<[u8; 32]>());
+  let hash = seed_hash(&seed);
+
+  let _: () = conn.hset(SEEDS_KEY, &hash, &seed).await?;
+  let _: () = conn.hset(CURRENT_SEEDS_KEY, scope, &hash).await?;
+
+  Ok((hash, seed))
+}
+
+/// The hash and seed `scope` is using, making one if there isn't one yet
+async fn current_seed(conn: &mut Connection, scope: u64) -> Result<(String, String), RedisError> {
+  let hash: Option<String> = conn.hget(CURRENT_SEEDS_KEY, scope).await?;
+
+  if let Some(hash) = hash {
+    let seed: Option<String> = conn.hget(SEEDS_KEY, &hash).await?;
+
+    if let Some(seed) = seed {
+      return Ok((hash, seed));
+    }
   }
 
-  let count = match caps.name("count").unwrap().as_str().parse::<u32>() {
-    Ok(int) => int,
-    Err(_) => return error_hash(roll_str, "Must provide a valid, nonnegative number of dice"),
+  new_seed(conn, scope).await
+}
+
+/// Rolls using this guild's seed, and keeps the roll so it can be verified
+async fn verifiable_roll(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  intro: &str,
+  rolls: &[&str],
+) -> Result<(), String> {
+  let interaction_id = interaction.id.0;
+  let scope = seed_scope(interaction);
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
   };
 
-  if count == 0 {
-    return error_hash(
-      roll_str,
-      "I *can* roll zero dice, but am morally obligated not to",
-    );
+  let mut redis_client = lock.lock().await;
+
+  let (hash, seed) = current_seed(&mut redis_client.0, scope)
+    .await
+    .map_err(|err| err.to_string())?;
+
+  let footer = format!(
+    "Roll ID `{}`, seed hash `{}`. Check it with `/roll verify`",
+    interaction_id, hash
+  );
+
+  let rolled = roll_message(
+    intro,
+    rolls,
+    footer.len(),
+    &mut verifiable_rng(&seed, interaction_id),
+  )?;
+
+  let record = VerifiableRoll {
+    seed_hash: hash.clone(),
+    scope,
+    user_id: interaction.user.id.0,
+    rolls: rolls.iter().map(|roll| String::from(*roll)).collect(),
+    total: rolled.total,
+  };
+
+  let serialized = serde_json::to_string(&record).expect("Rolls are serializable");
+
+  let result: Result<(), RedisError> = redis_client
+    .0
+    .set_ex(
+      verifiable_roll_key(interaction_id),
+      serialized,
+      VERIFIABLE_ROLL_SECONDS,
+    )
+    .await;
+
+  drop(redis_client);
+
+  if let Err(error) = result {
+    return Err(format!("Could not save your roll: {}", error));
   }
 
-  let addition = match caps.name("addition") {
-    Some(number) => match number.as_str().parse::<i32>() {
-      Ok(int) => int,
-      Err(_) => return error_hash(roll_str, "Modifier must be a number"),
-    },
-    None => 0,
+  log_rolls(ctx, interaction, &rolled.rolls).await;
+
+  let message = format!("{}{}", rolled.message, footer);
+
+  send_roll(ctx, interaction, message).await
+}
+
+/// Reveals the seed of a verifiable roll (replacing it, if it's still in use),
+/// and rolls it again to show it comes out the same
+async fn verify_roll(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let id = get_str_or_error(
+    &interaction.data.options[0]
+      .options
+      .iter()
+      .find(|option| option.name == "id")
+      .and_then(|option| option.value.clone()),
+    "You must give the ID of a roll",
+  )?;
+
+  let interaction_id = id
+    .trim()
+    .parse::<u64>()
+    .map_err(|_| format!("'{}' is not a roll ID", id))?;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
   };
 
-  let low = match caps.name("low") {
-    Some(value) => {
-      let string_val = value.as_str();
-      if string_val == "" {
-        1
-      } else {
-        match string_val.parse::<u32>() {
-          Ok(int) => int,
-          Err(_) => return error_hash(roll_str, "Low drop must be a number"),
-        }
+  let (record, seed) = {
+    let mut redis_client = lock.lock().await;
+
+    let serialized: Option<String> = redis_client
+      .0
+      .get(verifiable_roll_key(interaction_id))
+      .await
+      .map_err(|err| err.to_string())?;
+
+    let record: VerifiableRoll = match serialized {
+      Some(serialized) => serde_json::from_str(&serialized).map_err(|err| err.to_string())?,
+      None => {
+        return Err(String::from(
+          "I don't have a verifiable roll with that ID. They are kept for 90 days",
+        ))
       }
+    };
+
+    let seed: Option<String> = redis_client
+      .0
+      .hget(SEEDS_KEY, &record.seed_hash)
+      .await
+      .map_err(|err| err.to_string())?;
+
+    let seed = seed.ok_or_else(|| String::from("The seed for this roll is missing"))?;
+
+    // The seed can't be used for anything else once it's revealed
+    let current: Option<String> = redis_client
+      .0
+      .hget(CURRENT_SEEDS_KEY, record.scope)
+      .await
+      .map_err(|err| err.to_string())?;
+
+    if current.as_ref() == Some(&record.seed_hash) {
+      new_seed(&mut redis_client.0, record.scope)
+        .await
+        .map_err(|err| err.to_string())?;
     }
-    None => 0,
+
+    (record, seed)
   };
 
-  let high = match caps.name("high") {
-    Some(value) => {
-      let string_val = value.as_str();
-      if string_val == "" {
-        1
-      } else {
-        match string_val.parse::<u32>() {
-          Ok(int) => int,
-          Err(_) => return error_hash(roll_str, "High drop must be a number"),
-        }
-      }
+  let hash_matches = seed_hash(&seed) == record.seed_hash;
+
+  let header = format!(
+    "Roll `{}` by <@{}>: `{}`, which came to **{}**\nSeed `{}`, whose SHA-256 hash {} `{}`\nThe dice come from ChaCha20, seeded with the SHA-256 hash of `{}:{}`\n",
+    interaction_id,
+    record.user_id,
+    record.rolls.join("`, `"),
+    record.total,
+    seed,
+    if hash_matches { "is" } else { "**is not**" },
+    record.seed_hash,
+    seed,
+    interaction_id
+  );
+
+  let rolls: Vec<&str> = record.rolls.iter().map(String::as_str).collect();
+  let rolled = roll_message(
+    "Rolling it again gives",
+    &rolls,
+    header.chars().count(),
+    &mut verifiable_rng(&seed, interaction_id),
+  )?;
+
+  let message = format!("{}{}", header, rolled.message);
+
+  send_roll(ctx, interaction, message).await
+}
+
+/// A roll in a channel's history
+#[derive(Deserialize, Serialize)]
+struct LoggedRoll {
+  user_id: u64,
+  /// Their name when they rolled
+  user: String,
+  expr: String,
+  total: i64,
+  natural_20s: u32,
+  /// When it was rolled, as a UNIX timestamp
+  time: i64,
+}
+
+/// A channel's rolls (as JSON), newest first
+fn history_key(channel_id: u64) -> String {
+  format!("rolls:history:{}", channel_id)
+}
+
+/// Adds rolls to the channel's history. Rolling still works if this fails
+async fn log_rolls(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+  rolls: &[(String, Roll)],
+) {
+  let user = &interaction.user;
+  let time = Utc::now().timestamp();
+
+  let entries: Vec<String> = rolls
+    .iter()
+    .map(|(expr, roll)| {
+      let logged = LoggedRoll {
+        user_id: user.id.0,
+        user: user.name.clone(),
+        expr: expr.clone(),
+        total: roll.total,
+        natural_20s: roll.natural_20s,
+        time,
+      };
+
+      serde_json::to_string(&logged).expect("Rolls are serializable")
+    })
+    .collect();
+
+  let key = history_key(interaction.channel_id.0);
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let result: Result<(), RedisError> = {
+    let mut redis_client = lock.lock().await;
+
+    pipe()
+      .atomic()
+      .lpush(&key, entries)
+      .ignore()
+      .ltrim(&key, 0, MAX_HISTORY - 1)
+      .ignore()
+      .query_async(&mut redis_client.0)
+      .await
+  };
+
+  if let Err(error) = result {
+    println!("Could not log rolls: {}", error);
+  }
+}
+
+async fn get_history(ctx: &Context, channel_id: u64) -> Result<Vec<LoggedRoll>, String> {
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let entries: Vec<String> = {
+    let mut redis_client = lock.lock().await;
+    redis_client
+      .0
+      .lrange(history_key(channel_id), 0, MAX_HISTORY - 1)
+      .await
+      .map_err(|err| err.to_string())?
+  };
+
+  Ok(
+    entries
+      .iter()
+      .filter_map(|entry| serde_json::from_str(entry).ok())
+      .collect(),
+  )
+}
+
+async fn roll_history(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options[0].options;
+
+  let user_id = options
+    .iter()
+    .find(|option| option.name == "user")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .and_then(|id| id.parse::<u64>().ok());
+
+  let limit = options
+    .iter()
+    .find(|option| option.name == "limit")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_u64())
+    .map_or(10, |limit| (limit as usize).min(MAX_HISTORY_SHOWN));
+
+  let history = get_history(ctx, interaction.channel_id.0).await?;
+
+  let shown: Vec<&LoggedRoll> = history
+    .iter()
+    .filter(|logged| user_id.map_or(true, |user_id| logged.user_id == user_id))
+    .take(limit)
+    .collect();
+
+  let message = if shown.is_empty() {
+    String::from("There aren't any rolls here yet")
+  } else {
+    let mut message = String::from("Latest rolls here:\n");
+
+    for logged in shown {
+      message += &format!(
+        "<t:{}:R> **{}**: `{}` = **{}**\n",
+        logged.time, logged.user, logged.expr, logged.total
+      );
     }
-    None => 0,
+
+    message
   };
 
-  if low + high >= count {
-    return error_hash(
-      roll_str,
-      &format!(
-        "You want to drop {} dice but are only rolling {}. What are you even doing?",
-        low + high,
-        count
-      ),
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+/// One player's rolls in `/roll export`
+struct PlayerSummary<'a> {
+  /// Their name when they last rolled
+  name: &'a str,
+  rolls: u32,
+  total: i64,
+  highest: i64,
+  lowest: i64,
+  natural_20s: u32,
+}
+
+/// Quotes a CSV field, if it needs it
+fn csv_field(text: &str) -> String {
+  if text.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+    format!("\"{}\"", text.replace('"', "\"\""))
+  } else {
+    String::from(text)
+  }
+}
+
+async fn export_rolls(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let hours = interaction.data.options[0]
+    .options
+    .iter()
+    .find(|option| option.name == "hours")
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_u64())
+    .map_or(24, |hours| (hours as usize).min(MAX_EXPORT_HOURS));
+
+  let since = (Utc::now() - Duration::hours(hours as i64)).timestamp();
+
+  let mut history = get_history(ctx, interaction.channel_id.0).await?;
+  history.retain(|logged| logged.time >= since);
+  history.reverse();
+
+  if history.is_empty() {
+    return Err(format!(
+      "No one has rolled here in the last {} hours",
+      hours
+    ));
+  }
+
+  let mut rolls_csv = String::from("time,player,roll,total,natural 20s\n");
+
+  // By ID, so someone who changed their name partway is still one player
+  let mut players: BTreeMap<u64, PlayerSummary> = BTreeMap::new();
+
+  for logged in &history {
+    let time = Utc
+      .timestamp_opt(logged.time, 0)
+      .single()
+      .map(|time| time.to_rfc3339())
+      .unwrap_or_default();
+
+    rolls_csv += &format!(
+      "{},{},{},{},{}\n",
+      time,
+      csv_field(&logged.user),
+      csv_field(&logged.expr),
+      logged.total,
+      logged.natural_20s
+    );
+
+    let player = players.entry(logged.user_id).or_insert(PlayerSummary {
+      name: &logged.user,
+      rolls: 0,
+      total: 0,
+      highest: i64::MIN,
+      lowest: i64::MAX,
+      natural_20s: 0,
+    });
+    // The history is oldest first, so this ends up as their latest name
+    player.name = &logged.user;
+    player.rolls += 1;
+    player.total = player.total.saturating_add(logged.total);
+    player.highest = player.highest.max(logged.total);
+    player.lowest = player.lowest.min(logged.total);
+    player.natural_20s += logged.natural_20s;
+  }
+
+  let mut summary_csv = String::from("player,rolls,average,highest,lowest,natural 20s\n");
+
+  let mut players: Vec<PlayerSummary> = players.into_values().collect();
+  players.sort_by_key(|summary| summary.name);
+
+  for summary in players {
+    summary_csv += &format!(
+      "{},{},{:.2},{},{},{}\n",
+      csv_field(summary.name),
+      summary.rolls,
+      summary.total as f64 / summary.rolls as f64,
+      summary.highest,
+      summary.lowest,
+      summary.natural_20s
+    );
+  }
+
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| {
+          msg
+            .content(format!(
+              "Here are the {} rolls in this channel from the last {} hours, and a summary for each player",
+              history.len(),
+              hours
+            ))
+            .add_file((summary_csv.as_bytes(), "session-summary.csv"))
+            .add_file((rolls_csv.as_bytes(), "session-rolls.csv"))
+            .ephemeral(true)
+        })
+    })
+    .await;
+
+  Ok(())
+}
+
+/// Works out the exact odds of `roll_str`, and describes them
+/// # Arguments
+/// - `roll_str` - the roll, like 4d6kh3
+/// - `target` - a total to give the chance of rolling at least
+fn roll_stats(roll_str: &str, target: Option<i64>) -> Result<String, String> {
+  let expr = parse(roll_str).map_err(|error| error.render(roll_str))?;
+  let distribution = expr
+    .distribution()
+    .map_err(|error| format!("**{}**", error))?;
+
+  let mut message = format!(
+    "**`{}`**\nAverage **{:.2}**, standard deviation **{:.2}**\nFrom **{}** to **{}**{}\n",
+    expr,
+    distribution.mean(),
+    distribution.std_dev(),
+    distribution.min(),
+    distribution.max(),
+    if distribution.open_ended {
+      " (exploding dice can very rarely go further)"
+    } else {
+      ""
+    }
+  );
+
+  if let Some(target) = target {
+    message += &format!(
+      "Chance of rolling {} or more: **{}**\n",
+      target,
+      percent(distribution.at_least(target))
     );
   }
 
-  let mut rolls: Vec<i32> = Vec::new();
-  let mut rng = thread_rng();
+  message += &format!("```\n{}```", histogram(&distribution));
+
+  Ok(message)
+}
 
-  for _ in 0..count {
-    let result = rng.gen_range(1..=size) as i32;
-    rolls.push(result);
+/// Shows a chance as a percentage, without rounding anything possible to 0
+fn percent(chance: f64) -> String {
+  if chance <= 0.0 {
+    String::from("0%")
+  } else if chance < 0.0001 {
+    String::from("<0.01%")
+  } else {
+    format!("{:.2}%", chance * 100.0)
   }
+}
 
-  rolls.sort();
+/// Draws a bar chart of the chance of each total (or group of totals)
+fn histogram(distribution: &Distribution) -> String {
+  // Leave off the (very) unlikely ends, so there's something to see
+  let likely: Vec<i64> = distribution
+    .chances
+    .iter()
+    .filter(|(_, chance)| **chance >= HISTOGRAM_CUTOFF)
+    .map(|(total, _)| *total)
+    .collect();
 
-  let meaningful_rolls = if low > 0 || high > 0 {
-    &rolls[(low as usize)..(rolls.len() - (high as usize))]
+  let (low, high) = match (likely.first(), likely.last()) {
+    (Some(low), Some(high)) => (*low, *high),
+    _ => (distribution.min(), distribution.max()),
+  };
+
+  // Round up, so that there are at most `MAX_HISTOGRAM_BARS`. This is done in
+  // i128, since the totals can be too far apart to subtract in i64
+  let (low, high) = (i128::from(low), i128::from(high));
+  let width = ((high - low) / i128::from(MAX_HISTOGRAM_BARS)) + 1;
+
+  let bars: Vec<(String, f64)> = if likely.len() as i64 <= MAX_HISTOGRAM_BARS {
+    // Few enough to show each total, even if they are spread out
+    likely
+      .iter()
+      .map(|total| (total.to_string(), distribution.chances[total]))
+      .collect()
   } else {
-    &rolls[..]
+    (0..)
+      .map(|bar| low + bar * width)
+      .take_while(|start| *start <= high)
+      .map(|start| {
+        let end = start + width - 1;
+
+        let label = if width == 1 {
+          start.to_string()
+        } else {
+          format!("{} to {}", start, end)
+        };
+
+        // Both fit in an i64, since they're between `low` and `high`
+        let chance = distribution
+          .chances
+          .range(start as i64..=end.min(high) as i64)
+          .map(|(_, chance)| chance)
+          .sum();
+
+        (label, chance)
+      })
+      .collect()
   };
 
-  let sum: i32 = meaningful_rolls.iter().sum::<i32>();
-  let avg = (sum as f64) / (meaningful_rolls.len() as f64);
+  let label_width = bars
+    .iter()
+    .map(|(label, _)| label.len())
+    .max()
+    .unwrap_or_default();
+  let most_likely = bars.iter().map(|(_, chance)| *chance).fold(0.0, f64::max);
 
-  let mut result_str = format!("{}d{}", count, size);
+  let mut chart = String::new();
 
-  if addition != 0 {
-    result_str += &format!(" + {}", addition);
+  for (label, chance) in bars {
+    let length = (chance / most_likely * HISTOGRAM_WIDTH).round() as usize;
+
+    chart += &format!(
+      "{:>label_width$} {:>7} {}\n",
+      label,
+      percent(chance),
+      "#".repeat(length),
+      label_width = label_width
+    );
   }
 
-  if low > 0 {
-    result_str += &format!(", drop {} low", low);
+  chart
+}
+
+/// Someone's macros, either everywhere (`guild_id` of `None`) or in one server
+fn macro_key(user_id: u64, guild_id: Option<u64>) -> String {
+  match guild_id {
+    Some(guild_id) => format!("rolls:macros:{}:{}", user_id, guild_id),
+    None => format!("rolls:macros:{}", user_id),
   }
+}
+
+/// Someone's macros that can be used here: this server's, then everywhere's.
+/// A macro in this server hides one with the same name from everywhere
+async fn get_macros(
+  conn: &mut Connection,
+  user_id: u64,
+  guild_id: Option<u64>,
+) -> Result<Vec<RollMacro>, RedisError> {
+  let mut macros: Vec<RollMacro> = vec![];
+
+  let mut scopes = vec![(None, false)];
 
-  if high > 0 {
-    result_str += &format!(", drop {} high", high);
+  if let Some(guild_id) = guild_id {
+    scopes.insert(0, (Some(guild_id), true));
   }
 
-  let full_rolls = pretty_vec(meaningful_rolls);
+  for (scope, server) in scopes {
+    let saved: HashMap<String, String> = conn.hgetall(macro_key(user_id, scope)).await?;
 
-  result_str += &format!(" = **{}**\n{} (avg {})\n", sum + addition, full_rolls, avg);
+    let mut saved: Vec<RollMacro> = saved
+      .into_iter()
+      .filter(|(name, _)| !macros.iter().any(|existing| existing.name == *name))
+      .map(|(name, expr)| RollMacro { name, expr, server })
+      .collect();
 
-  if low > 0 {
-    let low_set = &rolls[0..(low as usize)];
-    let low_sum = low_set.iter().sum::<i32>();
-    let low_avg = (low_sum as f64) / (low_set.len() as f64);
-    result_str += &format!("Dropped low: {} (avg {})\n", pretty_vec(low_set), low_avg);
+    saved.sort_by(|a, b| a.name.cmp(&b.name));
+    macros.extend(saved);
   }
 
-  if high > 0 {
-    let high_set = &rolls[rolls.len() - (high as usize)..];
-    let high_sum = high_set.iter().sum::<i32>();
-    let high_avg = (high_sum as f64) / (high_set.len() as f64);
-    result_str += &format!("Dropped low: {} (avg {})\n", pretty_vec(high_set), high_avg);
+  Ok(macros)
+}
+
+/// Macro names are case insensitive, and only letters, numbers, - and _
+fn macro_name(name: &str) -> Result<String, String> {
+  let name = name.trim().to_lowercase();
+
+  if name.is_empty()
+    || name.chars().count() > MAX_MACRO_NAME_LENGTH as usize
+    || !name
+      .chars()
+      .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+  {
+    return Err(format!(
+      "'{}' can't be a macro name. Use up to {} letters, numbers, - and _",
+      name, MAX_MACRO_NAME_LENGTH
+    ));
   }
 
-  Ok((sum, result_str))
+  Ok(name)
 }
 
-/// Hashes and rolls an invalid die roll and returns the error message.
-/// This will always return an error
-/// # Arguments
-/// - die:    a string representing an invalid die roll, to be hashed
-/// - error:  the error message corresponding to the roll
-fn error_hash(die: &str, error: &str) -> Result<(i32, String), String> {
-  let mut s = DefaultHasher::new();
-  die.hash(&mut s);
+async fn interaction_roll_macro(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let subcommand = match interaction.data.options[0].options.get(0) {
+    Some(subcommand) => subcommand,
+    None => return Err(String::from("Must have subcommand")),
+  };
 
-  let hashed_val = (s.finish() % 1000) as u32;
+  let user_id = interaction.user.id.0;
+  let guild_id = interaction.guild_id.map(|guild| guild.0);
 
-  let rng = thread_rng().gen_range(1..=hashed_val);
+  let option = |name: &str| {
+    subcommand
+      .options
+      .iter()
+      .find(|option| option.name == name)
+      .and_then(|option| option.value.clone())
+  };
 
-  Err(format!(
-    "**{}**.\nBut here's a guess for {} = 1d{}: **{}**",
-    error, die, hashed_val, rng
-  ))
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let message = match subcommand.name.as_str() {
+    "save" => {
+      let name = macro_name(&get_str_or_error(
+        &option("name"),
+        "You must give the macro a name",
+      )?)?;
+      let expr = get_str_or_error(&option("expr"), "You must give a roll to save")?;
+
+      // Check it now, rather than every time it's rolled
+      if let Err(error) = parse(&expr) {
+        return Err(error.render(&expr));
+      }
+
+      let server = option("server").and_then(|server| server.as_bool()) == Some(true);
+
+      let scope = match (server, guild_id) {
+        (true, Some(guild_id)) => Some(guild_id),
+        (true, None) => return Err(String::from("Server macros can only be saved in a server")),
+        (false, _) => None,
+      };
+
+      let key = macro_key(user_id, scope);
+
+      let result: Result<(), RedisError> = {
+        let mut redis_client = lock.lock().await;
+
+        let exists: bool = redis_client
+          .0
+          .hexists(&key, &name)
+          .await
+          .map_err(|err| err.to_string())?;
+        let count: usize = redis_client
+          .0
+          .hlen(&key)
+          .await
+          .map_err(|err| err.to_string())?;
+
+        if !exists && count >= MAX_MACROS {
+          return Err(format!(
+            "You can only have {} macros {}. Delete one first",
+            MAX_MACROS,
+            if server { "in a server" } else { "everywhere" }
+          ));
+        }
+
+        redis_client.0.hset(&key, &name, &expr).await
+      };
+
+      if let Err(error) = result {
+        return Err(error.to_string());
+      }
+
+      format!(
+        "Saved `{}` as **{}**{}. Roll it with `/roll macro run`",
+        expr,
+        name,
+        if server { " for this server" } else { "" }
+      )
+    }
+    "run" => {
+      let name = get_str_or_error(&option("name"), "You must give a macro to roll")?;
+      let name = name.trim().to_lowercase();
+
+      let macros = {
+        let mut redis_client = lock.lock().await;
+        get_macros(&mut redis_client.0, user_id, guild_id)
+          .await
+          .map_err(|err| err.to_string())?
+      };
+
+      let expr = match macros.iter().find(|existing| existing.name == name) {
+        Some(existing) => &existing.expr,
+        None => return Err(format!("You don't have a macro called '{}'", name)),
+      };
+
+      let mention = get_mention(interaction);
+      let rolled = roll_message(
+        &format!("{}, you rolled **{}** for", mention, name),
+        &[expr.as_str()],
+        0,
+        &mut thread_rng(),
+      )?;
+
+      log_rolls(ctx, interaction, &rolled.rolls).await;
+
+      return send_roll(ctx, interaction, rolled.message).await;
+    }
+    "list" => {
+      let macros = {
+        let mut redis_client = lock.lock().await;
+        get_macros(&mut redis_client.0, user_id, guild_id)
+          .await
+          .map_err(|err| err.to_string())?
+      };
+
+      if macros.is_empty() {
+        String::from("You don't have any macros. Save one with `/roll macro save`")
+      } else {
+        let mut message = String::from("Your macros:\n");
+
+        for saved in macros {
+          message += &format!(
+            "- **{}**: `{}`{}\n",
+            saved.name,
+            saved.expr,
+            if saved.server { " (this server)" } else { "" }
+          );
+        }
+
+        message
+      }
+    }
+    "delete" => {
+      let name = get_str_or_error(&option("name"), "You must give a macro to delete")?;
+      let name = name.trim().to_lowercase();
+
+      let result: Result<bool, RedisError> = {
+        let mut redis_client = lock.lock().await;
+
+        let mut deleted = false;
+
+        if let Some(guild_id) = guild_id {
+          let removed: u32 = redis_client
+            .0
+            .hdel(macro_key(user_id, Some(guild_id)), &name)
+            .await
+            .map_err(|err| err.to_string())?;
+          deleted = removed > 0;
+        }
+
+        if deleted {
+          Ok(true)
+        } else {
+          redis_client
+            .0
+            .hdel(macro_key(user_id, None), &name)
+            .await
+            .map(|removed: u32| removed > 0)
+        }
+      };
+
+      match result {
+        Ok(true) => format!("Deleted **{}**", name),
+        Ok(false) => return Err(format!("You don't have a macro called '{}'", name)),
+        Err(error) => return Err(error.to_string()),
+      }
+    }
+    _ => return Err(String::from("Unexpected command")),
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |response| {
+      response
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
 }
 
-/// Creates a String representation of `numbers`, separated by commas
-/// # Arguments
-/// - `numbers` - a vector of numbers
-fn pretty_vec(numbers: &[i32]) -> String {
-  let string_list: Vec<String> = numbers.iter().map(|number| number.to_string()).collect();
+/// Suggests macro names for `/roll macro run` and `/roll macro delete`
+pub async fn autocomplete_roll(
+  ctx: &Context,
+  interaction: &AutocompleteInteraction,
+) -> Result<(), String> {
+  let typed = interaction
+    .data
+    .options
+    .get(0)
+    .and_then(|group| group.options.get(0))
+    .and_then(|subcommand| subcommand.options.iter().find(|option| option.focused))
+    .and_then(|option| option.value.as_ref())
+    .and_then(|value| value.as_str())
+    .unwrap_or_default()
+    .trim()
+    .to_lowercase();
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let macros = {
+    let mut redis_client = lock.lock().await;
+    get_macros(
+      &mut redis_client.0,
+      interaction.user.id.0,
+      interaction.guild_id.map(|guild| guild.0),
+    )
+    .await
+    .map_err(|err| err.to_string())?
+  };
+
+  let _ = interaction
+    .create_autocomplete_response(ctx, |response| {
+      for saved in macros
+        .iter()
+        .filter(|saved| saved.name.contains(&typed))
+        .take(MAX_MACROS)
+      {
+        // Choice names can be at most 100 characters
+        let label: String = format!("{} ({})", saved.name, saved.expr)
+          .chars()
+          .take(100)
+          .collect();
+        response.add_string_choice(label, &saved.name);
+      }
 
-  string_list.join(", ")
+      response
+    })
+    .await;
+
+  Ok(())
 }
@@ -0,0 +1,570 @@
+use crate::RedisConnectionKey;
+
+use redis::{aio::Connection, cmd, pipe, AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use serenity::{
+  builder::{CreateApplicationCommands, CreateEmbed},
+  model::{
+    application::{
+      command::*,
+      interaction::{application_command::*, *},
+    },
+    channel::{Channel, ChannelType, Message, Reaction, ReactionType},
+    id::{ChannelId, MessageId},
+  },
+  prelude::*,
+  utils::{parse_emoji, Colour},
+};
+
+const STARBOARD_CONFIG_KEY: &str = "safety:starboard:config";
+
+/// The starboard post for each starred message in a server, as
+/// `{channel}:{message}` since the starboard channel can change
+fn posts_key(guild_id: u64) -> String {
+  format!("safety:starboard:posts:{}", guild_id)
+}
+
+fn parse_post(post: &str) -> Option<(u64, u64)> {
+  let (channel_id, message_id) = post.split_once(':')?;
+  Some((channel_id.parse().ok()?, message_id.parse().ok()?))
+}
+
+/// Held by whoever is posting a message to the starboard, followed by its ID.
+/// It expires in case the bot stops partway
+const STARBOARD_CLAIM_PREFIX: &str = "safety:starboard:claim:";
+const STARBOARD_CLAIM_SECONDS: u64 = 60;
+
+const DEFAULT_EMOJI: &str = "⭐";
+const DEFAULT_THRESHOLD: u64 = 3;
+const MAX_THRESHOLD: u64 = 100;
+
+pub fn starboard_command(
+  commands: &mut CreateApplicationCommands,
+) -> &mut CreateApplicationCommands {
+  commands.create_application_command(|command| {
+    command
+      .name("starboard")
+      .description("Repost messages that get enough reactions to a starboard channel")
+      .create_option(|op| {
+        op.name("config")
+          .kind(CommandOptionType::SubCommand)
+          .description("(Manage Server only) Set up this server's starboard")
+          .create_sub_option(|channel| {
+            channel
+              .name("channel")
+              .kind(CommandOptionType::Channel)
+              .channel_types(&[ChannelType::Text])
+              .description("The channel to repost messages in")
+              .required(false)
+          })
+          .create_sub_option(|emoji| {
+            emoji
+              .name("emoji")
+              .kind(CommandOptionType::String)
+              .description("The reaction that counts (default ⭐)")
+              .required(false)
+          })
+          .create_sub_option(|threshold| {
+            threshold
+              .name("threshold")
+              .kind(CommandOptionType::Integer)
+              .description("How many reactions a message needs (default 3)")
+              .min_int_value(1)
+              .max_int_value(MAX_THRESHOLD)
+              .required(false)
+          })
+      })
+      .create_option(|op| {
+        op.name("disable")
+          .kind(CommandOptionType::SubCommand)
+          .description("(Manage Server only) Turn off this server's starboard")
+      })
+  })
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StarboardConfig {
+  channel: u64,
+  /// A unicode emoji, or a custom one like `<:name:id>`
+  emoji: String,
+  threshold: u64,
+}
+
+impl StarboardConfig {
+  fn matches(&self, reaction: &ReactionType) -> bool {
+    match reaction {
+      ReactionType::Custom { id, .. } => {
+        parse_emoji(&self.emoji).is_some_and(|emoji| emoji.id == *id)
+      }
+      // Some keyboards add a variation selector and some don't
+      ReactionType::Unicode(emoji) => {
+        emoji.trim_end_matches('\u{fe0f}') == self.emoji.trim_end_matches('\u{fe0f}')
+      }
+      _ => false,
+    }
+  }
+}
+
+async fn get_config(con: &mut Connection, guild_id: u64) -> RedisResult<Option<StarboardConfig>> {
+  let config: Option<String> = con.hget(STARBOARD_CONFIG_KEY, guild_id).await?;
+
+  Ok(config.and_then(|config| serde_json::from_str(&config).ok()))
+}
+
+/// Checks that `emoji` is something that could be reacted with
+fn parse_starboard_emoji(emoji: &str) -> Result<String, String> {
+  let emoji = emoji.trim();
+
+  if parse_emoji(emoji).is_some() {
+    return Ok(String::from(emoji));
+  }
+
+  let is_unicode = !emoji.is_empty()
+    && emoji.chars().count() <= 8
+    && emoji
+      .chars()
+      .all(|c| !c.is_ascii() || c.is_ascii_digit() || c == '#' || c == '*');
+
+  if is_unicode {
+    Ok(String::from(emoji))
+  } else {
+    Err(format!("'{}' is not an emoji", emoji))
+  }
+}
+
+pub async fn interaction_starboard(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options;
+
+  if options.is_empty() {
+    return Err(String::from("Must have subcommand"));
+  }
+
+  let guild_id = match interaction.guild_id {
+    Some(guild_id) => guild_id.0,
+    None => return Err(String::from("The starboard only works in servers")),
+  };
+
+  let is_admin = interaction
+    .member
+    .as_ref()
+    .and_then(|member| member.permissions)
+    .is_some_and(|permissions| permissions.manage_guild());
+
+  if !is_admin {
+    return Err(String::from(
+      "You need the Manage Server permission to do this",
+    ));
+  }
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let mut redis_client = lock.lock().await;
+
+  let message = match options[0].name.as_str() {
+    "config" => {
+      let existing = get_config(&mut redis_client.0, guild_id)
+        .await
+        .map_err(|err| err.to_string())?;
+
+      let option = |name: &str| {
+        options[0]
+          .options
+          .iter()
+          .find(|option| option.name == name)
+          .and_then(|option| option.value.as_ref())
+      };
+
+      let channel = match option("channel").and_then(|channel| channel.as_str()) {
+        Some(channel) => channel
+          .parse::<u64>()
+          .map_err(|_| format!("'{}' is not a valid channel", channel))?,
+        None => match &existing {
+          Some(existing) => existing.channel,
+          None => {
+            return Err(String::from(
+              "You must pick a channel the first time you set up the starboard",
+            ))
+          }
+        },
+      };
+
+      let emoji = match option("emoji").and_then(|emoji| emoji.as_str()) {
+        Some(emoji) => parse_starboard_emoji(emoji)?,
+        None => existing
+          .as_ref()
+          .map_or(String::from(DEFAULT_EMOJI), |existing| {
+            existing.emoji.clone()
+          }),
+      };
+
+      let threshold = match option("threshold").and_then(|threshold| threshold.as_u64()) {
+        Some(threshold) => threshold.clamp(1, MAX_THRESHOLD),
+        None => existing
+          .as_ref()
+          .map_or(DEFAULT_THRESHOLD, |existing| existing.threshold),
+      };
+
+      let config = StarboardConfig {
+        channel,
+        emoji,
+        threshold,
+      };
+
+      let serialized = serde_json::to_string(&config).expect("Configs are serializable");
+
+      let result: RedisResult<()> = redis_client
+        .0
+        .hset(STARBOARD_CONFIG_KEY, guild_id, serialized)
+        .await;
+
+      if let Err(error) = result {
+        return Err(error.to_string());
+      }
+
+      format!(
+        "Messages with {} {} reactions will be reposted in <#{}>",
+        config.threshold, config.emoji, config.channel
+      )
+    }
+    "disable" => {
+      // Its posts are forgotten too, so they don't pile up
+      let result: RedisResult<()> = pipe()
+        .atomic()
+        .hdel(STARBOARD_CONFIG_KEY, guild_id)
+        .ignore()
+        .del(posts_key(guild_id))
+        .ignore()
+        .query_async(&mut redis_client.0)
+        .await;
+
+      if let Err(error) = result {
+        return Err(error.to_string());
+      }
+
+      String::from("Turned off the starboard")
+    }
+    _ => return Err(String::from("Unexpected command")),
+  };
+
+  drop(redis_client);
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
+
+fn starboard_content(config: &StarboardConfig, count: u64, message: &Message) -> String {
+  format!(
+    "{} **{}** in <#{}>",
+    config.emoji, count, message.channel_id.0
+  )
+}
+
+fn starboard_embed(guild_id: u64, message: &Message) -> CreateEmbed {
+  let mut embed = CreateEmbed::default();
+
+  let link = format!(
+    "[Jump to message](https://discord.com/channels/{}/{}/{})",
+    guild_id, message.channel_id.0, message.id.0
+  );
+
+  // Without the Message Content intent, the content is always empty
+  let description = if message.content.is_empty() {
+    link
+  } else {
+    format!("{}\n\n{}", message.content, link)
+  };
+
+  embed
+    .author(|author| {
+      author
+        .name(&message.author.name)
+        .icon_url(message.author.face())
+    })
+    .description(description)
+    .timestamp(message.timestamp)
+    .colour(Colour::GOLD);
+
+  if let Some(image) = message
+    .attachments
+    .iter()
+    .find(|attachment| attachment.width.is_some())
+  {
+    embed.image(&image.url);
+  } else if let Some(image) = message.embeds.iter().find_map(|quoted| {
+    quoted
+      .image
+      .as_ref()
+      .map(|image| image.url.clone())
+      .or_else(|| {
+        quoted
+          .thumbnail
+          .as_ref()
+          .map(|thumbnail| thumbnail.url.clone())
+      })
+  }) {
+    embed.image(image);
+  }
+
+  embed
+}
+
+/// How many of the starboard reaction a message has, read fresh from Discord
+async fn star_count(
+  ctx: &Context,
+  config: &StarboardConfig,
+  channel_id: ChannelId,
+  message_id: MessageId,
+) -> Result<(Message, u64), String> {
+  let message = channel_id
+    .message(ctx, message_id)
+    .await
+    .map_err(|err| format!("Could not get starred message: {}", err))?;
+
+  let count = message
+    .reactions
+    .iter()
+    .find(|existing| config.matches(&existing.reaction_type))
+    .map_or(0, |existing| existing.count);
+
+  Ok((message, count))
+}
+
+/// Shows the latest count on a starboard post. Posts stay up if the count
+/// drops, but show the new count
+async fn update_post(
+  ctx: &Context,
+  config: &StarboardConfig,
+  (channel_id, post_id): (u64, u64),
+  count: u64,
+  message: &Message,
+) -> Result<(), String> {
+  ChannelId(channel_id)
+    .edit_message(ctx, post_id, |msg| {
+      msg.content(starboard_content(config, count, message))
+    })
+    .await
+    .map(|_| ())
+    .map_err(|err| format!("Could not update starboard post: {}", err))
+}
+
+/// Reposts (or updates the count on) a message when someone reacts to it
+/// with the starboard emoji, or takes their reaction back
+pub async fn handle_star_reaction(ctx: &Context, reaction: &Reaction) -> Result<(), String> {
+  if reaction.guild_id.is_none() {
+    return Ok(());
+  }
+
+  update_starboard(
+    ctx,
+    reaction.channel_id,
+    reaction.message_id,
+    Some(&reaction.emoji),
+  )
+  .await
+}
+
+/// Updates the count on a message's starboard post when a moderator clears
+/// its reactions, either all of them or just one emoji's
+pub async fn handle_star_reactions_cleared(
+  ctx: &Context,
+  channel_id: ChannelId,
+  message_id: MessageId,
+  emoji: Option<&ReactionType>,
+) -> Result<(), String> {
+  update_starboard(ctx, channel_id, message_id, emoji).await
+}
+
+/// The part of `MESSAGE_REACTION_REMOVE_EMOJI` the starboard needs, which
+/// serenity doesn't have an event for yet
+#[derive(Deserialize)]
+pub struct ReactionRemoveEmoji {
+  pub channel_id: ChannelId,
+  pub message_id: MessageId,
+  pub emoji: ReactionType,
+}
+
+/// Reposts a message once it has enough of the starboard emoji, and keeps the
+/// count on its post up to date. `emoji` is the reaction that changed, or
+/// `None` if any of them could have
+async fn update_starboard(
+  ctx: &Context,
+  channel_id: ChannelId,
+  message_id: MessageId,
+  emoji: Option<&ReactionType>,
+) -> Result<(), String> {
+  let source = match channel_id.to_channel(ctx).await {
+    Ok(Channel::Guild(source)) => source,
+    _ => return Ok(()),
+  };
+
+  let guild_id = source.guild_id.0;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let config = {
+    let mut redis_client = lock.lock().await;
+    get_config(&mut redis_client.0, guild_id)
+      .await
+      .map_err(|err| err.to_string())?
+  };
+
+  let config = match config {
+    // Starring the starboard's own posts would repost them again
+    Some(config) if config.channel != channel_id.0 => config,
+    _ => return Ok(()),
+  };
+
+  if let Some(emoji) = emoji {
+    if !config.matches(emoji) {
+      return Ok(());
+    }
+  }
+
+  let (message, count) = star_count(ctx, &config, channel_id, message_id).await?;
+
+  let post: Option<String> = {
+    let mut redis_client = lock.lock().await;
+    redis_client
+      .0
+      .hget(posts_key(guild_id), message_id.0)
+      .await
+      .map_err(|err| err.to_string())?
+  };
+
+  if let Some(post) = post.as_deref().and_then(parse_post) {
+    return update_post(ctx, &config, post, count, &message).await;
+  }
+
+  if count < config.threshold {
+    return Ok(());
+  }
+
+  // NSFW messages only go to a starboard that's NSFW too
+  if source.is_nsfw() {
+    let starboard_nsfw = match ChannelId(config.channel).to_channel(ctx).await {
+      Ok(Channel::Guild(starboard)) => starboard.is_nsfw(),
+      _ => false,
+    };
+
+    if !starboard_nsfw {
+      return Ok(());
+    }
+  }
+
+  let claim = format!("{}{}", STARBOARD_CLAIM_PREFIX, message_id.0);
+
+  // Claimed so two reactions at once can't both post, without holding the
+  // connection while posting
+  let claimed: Option<String> = {
+    let mut redis_client = lock.lock().await;
+    cmd("SET")
+      .arg(&claim)
+      .arg(1)
+      .arg("NX")
+      .arg("EX")
+      .arg(STARBOARD_CLAIM_SECONDS)
+      .query_async(&mut redis_client.0)
+      .await
+      .map_err(|err| err.to_string())?
+  };
+
+  if claimed.is_none() {
+    return Ok(());
+  }
+
+  let posted = post_to_starboard(ctx, &config, guild_id, channel_id, message_id).await;
+
+  {
+    let mut redis_client = lock.lock().await;
+
+    let result: RedisResult<()> = match &posted {
+      Ok(Some((post, _))) => {
+        pipe()
+          .atomic()
+          .hset(
+            posts_key(guild_id),
+            message_id.0,
+            format!("{}:{}", post.channel_id.0, post.id.0),
+          )
+          .del(&claim)
+          .query_async(&mut redis_client.0)
+          .await
+      }
+      _ => redis_client.0.del(&claim).await,
+    };
+
+    if let Err(error) = result {
+      return Err(error.to_string());
+    }
+  }
+
+  let (post, posted_count) = match posted? {
+    Some(posted) => posted,
+    None => return Ok(()),
+  };
+
+  // Reactions that came in while this was posting saw the claim and left it
+  // alone, so catch up on them now that the post is recorded
+  let (message, count) = star_count(ctx, &config, channel_id, message_id).await?;
+
+  if count != posted_count {
+    update_post(
+      ctx,
+      &config,
+      (post.channel_id.0, post.id.0),
+      count,
+      &message,
+    )
+    .await?;
+  }
+
+  Ok(())
+}
+
+/// Posts a message to the starboard if it still has enough reactions, giving
+/// the post and the count on it. The count is read again, since it may have
+/// changed while the post was claimed
+async fn post_to_starboard(
+  ctx: &Context,
+  config: &StarboardConfig,
+  guild_id: u64,
+  channel_id: ChannelId,
+  message_id: MessageId,
+) -> Result<Option<(Message, u64)>, String> {
+  let (message, count) = star_count(ctx, config, channel_id, message_id).await?;
+
+  if count < config.threshold {
+    return Ok(None);
+  }
+
+  ChannelId(config.channel)
+    .send_message(ctx, |msg| {
+      msg
+        .content(starboard_content(config, count, &message))
+        .set_embed(starboard_embed(guild_id, &message))
+    })
+    .await
+    .map(|post| Some((post, count)))
+    .map_err(|err| format!("Could not post to the starboard: {}", err))
+}
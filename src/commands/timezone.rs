@@ -0,0 +1,119 @@
+use crate::RedisConnectionKey;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use redis::{AsyncCommands, RedisError};
+use serenity::{
+  builder::CreateApplicationCommands,
+  model::application::{
+    command::*,
+    interaction::{application_command::*, *},
+  },
+  prelude::*,
+};
+
+use super::{birthday::BIRTHDAY_TZ_KEY, util::get_str_or_error};
+
+pub fn timezone_command(
+  commands: &mut CreateApplicationCommands,
+) -> &mut CreateApplicationCommands {
+  commands.create_application_command(|command| {
+    command
+      .name("timezone")
+      .description("Set your timezone, used for when your birthday is announced")
+      .create_option(|op| {
+        op.name("set")
+          .kind(CommandOptionType::SubCommand)
+          .description("Set your timezone")
+          .create_sub_option(|zone| {
+            zone
+              .name("zone")
+              .kind(CommandOptionType::String)
+              .description("Your timezone, like America/Chicago or Europe/London")
+              .required(true)
+          })
+      })
+      .create_option(|op| {
+        op.name("remove")
+          .kind(CommandOptionType::SubCommand)
+          .description("Remove your timezone (and go back to Eastern)")
+      })
+  })
+}
+
+pub async fn interaction_timezone(
+  ctx: &Context,
+  interaction: &ApplicationCommandInteraction,
+) -> Result<(), String> {
+  let options = &interaction.data.options;
+
+  if options.is_empty() {
+    return Err(String::from("Must have subcommand"));
+  }
+
+  let user_id = interaction.user.id.0;
+
+  let lock = {
+    let mut context = ctx.data.write().await;
+    context
+      .get_mut::<RedisConnectionKey>()
+      .expect("Expected redis connection")
+      .clone()
+  };
+
+  let message = match options[0].name.as_str() {
+    "set" => {
+      let zone_str = get_str_or_error(
+        &options[0].options.get(0).and_then(|op| op.value.clone()),
+        "You must provide a timezone",
+      )?;
+
+      let zone = zone_str.trim().parse::<Tz>().map_err(|_| {
+        format!(
+          "'{}' is not a timezone I know. Try something like America/New_York",
+          zone_str
+        )
+      })?;
+
+      let result: Result<(), RedisError> = {
+        let mut redis_client = lock.lock().await;
+        redis_client
+          .0
+          .hset(BIRTHDAY_TZ_KEY, user_id, zone.name())
+          .await
+      };
+
+      if let Err(error) = result {
+        return Err(error.to_string());
+      }
+
+      format!(
+        "Set your timezone to {} (it is currently {} there)",
+        zone.name(),
+        Utc::now().with_timezone(&zone).format("%-I:%M %P")
+      )
+    }
+    "remove" => {
+      let result: Result<(), RedisError> = {
+        let mut redis_client = lock.lock().await;
+        redis_client.0.hdel(BIRTHDAY_TZ_KEY, user_id).await
+      };
+
+      if let Err(error) = result {
+        return Err(error.to_string());
+      }
+
+      String::from("Removed your timezone")
+    }
+    _ => return Err(String::from("Unexpected command")),
+  };
+
+  let _ = interaction
+    .create_interaction_response(ctx, |f| {
+      f.kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|msg| msg.content(message).ephemeral(true))
+    })
+    .await;
+
+  Ok(())
+}
@@ -2,11 +2,9 @@ mod commands;
 mod util;
 
 use std::{
-  collections::HashMap,
   env::var,
   fs::OpenOptions,
   io::{Seek, Write},
-  ops::{Add, Sub},
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
@@ -14,17 +12,19 @@ use std::{
   time::Duration,
 };
 
-use chrono::{Datelike, Duration as ChronoDuration, NaiveDate, Timelike, Utc};
+use chrono::Utc;
 use chrono_tz::EST5EDT;
-use redis::{cmd, AsyncCommands, Client, RedisError};
+use redis::{cmd, Client, RedisError};
+use serde_json::Value;
 use serenity::{
   async_trait,
   client::Client as DiscordClient,
   gateway::ConnectionStage,
   http::Http,
   model::{
+    channel::Reaction,
     gateway::{Activity, GatewayIntents},
-    id::{ChannelId, GuildId},
+    id::{ChannelId, GuildId, MessageId},
     prelude::{
       command::Command,
       interaction::{Interaction, InteractionResponseType},
@@ -35,18 +35,19 @@ use serenity::{
 use tokio::{
   spawn,
   sync::{Mutex, RwLock},
-  time::{interval, sleep},
+  time::interval,
 };
 
 use commands::{
-  birthday::*, copy::*, help::*, link::*, news::*, nya::*, owo::*, poll::*, roll::*, unshittify::*,
+  birthday::*, copy::*, help::*, initiative::*, link::*, news::*, nya::*, owo::*, poll::*,
+  quote::*, roll::*, starboard::*, timezone::*, unshittify::*,
 };
 
 use util::{
+  leader::{Lease, LEASE_RENEW_SECS},
   rng::random_number,
   scheduler::{
-    Callable, MyVec, RedisConnectionKey, RedisSchedulerKey, RedisWrapper,
-    Scheduler as RedisScheduler,
+    Callable, RedisConnectionKey, RedisSchedulerKey, RedisWrapper, Scheduler as RedisScheduler,
   },
 };
 
@@ -64,13 +65,18 @@ impl EventHandler for Handler {
           "birthday" => interaction_birthday(&ctx, &app_command).await,
           "briefing" => interaction_briefing(&ctx, &app_command).await,
           "copy" => interaction_copy(&ctx, &app_command).await,
+          "copy to slot" => interaction_copy_slot(&ctx, &app_command).await,
           "help" => interaction_help(&ctx, &app_command).await,
+          "initiative" => interaction_initiative(&ctx, &app_command).await,
           "nya" => interaction_nya(&ctx, &app_command).await,
           "owo" => interaction_owo(&ctx, &app_command).await,
           "paste" => interaction_paste(&ctx, &app_command).await,
           "poll" => interaction_poll(&ctx, &app_command).await,
+          "quote" => interaction_quote(&ctx, &app_command).await,
           "roll" => interaction_roll(&ctx, &app_command).await,
           "sanitize" => interaction_sanitize(&ctx, &app_command).await,
+          "starboard" => interaction_starboard(&ctx, &app_command).await,
+          "timezone" => interaction_timezone(&ctx, &app_command).await,
           "unshitify" => interaciton_unshitify(&ctx, &app_command).await,
           _ => Err(format!("No command {}", command_name)),
         } {
@@ -90,7 +96,13 @@ impl EventHandler for Handler {
       Interaction::ModalSubmit(submit) => {
         if let Err(error) = match submit.data.custom_id.as_str() {
           "briefing" => interaction_briefing_followup(&ctx, &submit).await,
+          id if id.starts_with(BRIEFING_EDIT_PREFIX) => {
+            interaction_briefing_edit_followup(&ctx, &submit).await
+          }
           "options_add" => interaction_poll_add_followup(&ctx, &submit).await,
+          id if id.starts_with(COPY_SLOT_PREFIX) => {
+            interaction_copy_slot_followup(&ctx, &submit).await
+          }
           _ => Err(format!("No modal {}", submit.data.custom_id)),
         } {
           let _ = submit
@@ -111,6 +123,19 @@ impl EventHandler for Handler {
           "close" | "delete" => handle_poll_interaction(&ctx, &comp_inter).await,
           "add" => handle_poll_add(&ctx, &comp_inter).await,
           "toggle" => handle_poll_options_toggle(&ctx, &comp_inter).await,
+          id if id.starts_with(COPY_PICK_PREFIX) => handle_copy_pick(&ctx, &comp_inter).await,
+          INITIATIVE_NEXT | INITIATIVE_PREVIOUS => handle_initiative_turn(&ctx, &comp_inter).await,
+          id if id.starts_with(BRIEFING_EDIT_PREFIX) => {
+            handle_briefing_edit(&ctx, &comp_inter).await
+          }
+          id if id.starts_with(BRIEFING_DELETE_PREFIX) => {
+            handle_briefing_delete(&ctx, &comp_inter).await
+          }
+          id if id.starts_with(BRIEFING_APPROVE_PREFIX)
+            || id.starts_with(BRIEFING_REJECT_PREFIX) =>
+          {
+            handle_briefing_review(&ctx, &comp_inter).await
+          }
           _ => Ok(()),
         } {
           println!("An error occurred: {:?}", error);
@@ -128,10 +153,59 @@ impl EventHandler for Handler {
             .await;
         }
       }
+      Interaction::Autocomplete(autocomplete) => {
+        if let Err(error) = match autocomplete.data.name.as_str() {
+          "paste" => autocomplete_paste(&ctx, &autocomplete).await,
+          "roll" => autocomplete_roll(&ctx, &autocomplete).await,
+          _ => Ok(()),
+        } {
+          println!("An error occurred: {:?}", error);
+        }
+      }
       _ => {}
     }
   }
 
+  async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+    if let Err(error) = handle_star_reaction(&ctx, &reaction).await {
+      println!("Could not update starboard: {}", error);
+    }
+  }
+
+  async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+    if let Err(error) = handle_star_reaction(&ctx, &reaction).await {
+      println!("Could not update starboard: {}", error);
+    }
+  }
+
+  async fn reaction_remove_all(&self, ctx: Context, channel_id: ChannelId, message_id: MessageId) {
+    if let Err(error) = handle_star_reactions_cleared(&ctx, channel_id, message_id, None).await {
+      println!("Could not update starboard: {}", error);
+    }
+  }
+
+  async fn unknown(&self, ctx: Context, name: String, raw: Value) {
+    if name != "MESSAGE_REACTION_REMOVE_EMOJI" {
+      return;
+    }
+
+    let removed: ReactionRemoveEmoji = match serde_json::from_value(raw) {
+      Ok(removed) => removed,
+      Err(_) => return,
+    };
+
+    if let Err(error) = handle_star_reactions_cleared(
+      &ctx,
+      removed.channel_id,
+      removed.message_id,
+      Some(&removed.emoji),
+    )
+    .await
+    {
+      println!("Could not update starboard: {}", error);
+    }
+  }
+
   async fn cache_ready(&self, ctx: Context, _guilds: Vec<GuildId>) {
     if !self.loop_running.load(Ordering::Relaxed) {
       println!("Starting thread");
@@ -174,6 +248,18 @@ async fn main() {
     .parse::<u64>()
     .expect("Expected channel to be a number");
 
+  let birthday_hour = var("SAFETY_BIRTHDAY_HOUR")
+    .map(|hour| {
+      hour
+        .parse::<u32>()
+        .expect("Expected birthday hour to be a number")
+    })
+    .unwrap_or(0);
+
+  if birthday_hour > 23 {
+    panic!("Expected birthday hour to be between 0 and 23");
+  }
+
   let redis_url = var("SAFETY_REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1"));
 
   let token = &var("RUST_BOT").expect("token");
@@ -187,7 +273,18 @@ async fn main() {
 
   let http = Http::new_with_application_id(&token, app_id);
 
-  let intents = GatewayIntents::DIRECT_MESSAGES | GatewayIntents::GUILDS;
+  // The starboard needs to see reactions. It can only repost what messages say
+  // with the Message Content intent, which Discord refuses to connect with
+  // unless it's turned on for the bot in the developer portal. So it's only
+  // asked for when SAFETY_MESSAGE_CONTENT is set, and otherwise starboard
+  // posts just link to the message
+  let mut intents = GatewayIntents::DIRECT_MESSAGES
+    | GatewayIntents::GUILDS
+    | GatewayIntents::GUILD_MESSAGE_REACTIONS;
+
+  if var("SAFETY_MESSAGE_CONTENT").is_ok_and(|enabled| enabled == "true" || enabled == "1") {
+    intents |= GatewayIntents::MESSAGE_CONTENT;
+  }
 
   let mut client = DiscordClient::builder(&token, intents)
     .event_handler(Handler {
@@ -222,34 +319,89 @@ async fn main() {
       .await
       .expect("Should be able to create a second redis connection");
 
+    // Renewing the lease on its own connection keeps it from waiting on the
+    // shared one, which can be held for a while by other work
+    let mut lease_connection = redis_client
+      .get_async_connection()
+      .await
+      .expect("Should be able to create a redis connection for the leader lease");
+
     let redis_scheduler = RedisScheduler::new(connection);
 
     let redis_scheduler_arc = Arc::new(Mutex::new(redis_scheduler));
 
+    let conn_key = Arc::new(Mutex::new(RedisWrapper(persistent_connection)));
+
+    migrate_birthdays(&conn_key, guild_id, birthday_announce_channel)
+      .await
+      .expect("Expected to be able to set up birthday channel");
+
+    migrate_briefings(&conn_key, guild_id, birthday_announce_channel)
+      .await
+      .expect("Expected to be able to migrate briefings");
+
+    // Only one instance (the lease holder) runs the periodic loops below.
+    // Try to grab the lease up front so a lone instance does not skip the
+    // startup catch-up work
+    let lease = Arc::new(Lease::new());
+
+    if let Err(error) = lease.refresh(&mut lease_connection).await {
+      println!("Could not acquire leader lease: {:?}", error);
+    }
+
+    let lease_clone = lease.clone();
+
+    spawn(async move {
+      let mut interval: tokio::time::Interval = interval(Duration::from_secs(LEASE_RENEW_SECS));
+
+      loop {
+        interval.tick().await;
+
+        if let Err(error) = lease_clone.refresh(&mut lease_connection).await {
+          println!("Could not refresh leader lease: {:?}", error);
+        }
+      }
+    });
+
     let lock = redis_scheduler_arc.clone();
+    let scheduler_lease = lease.clone();
 
     spawn(async move {
       let mut interval: tokio::time::Interval = interval(Duration::from_secs(30));
 
       loop {
-        let jobs = {
-          let now = Utc::now().timestamp();
-          let mut task_scheduler = lock.lock().await;
-          task_scheduler.get_and_clear_ready_jobs(now).await
-        };
+        if scheduler_lease.is_leader() {
+          let (jobs, role_removals) = {
+            let now = Utc::now().timestamp();
+            let mut task_scheduler = lock.lock().await;
+            (
+              task_scheduler.get_and_clear_ready_jobs(now).await,
+              task_scheduler.get_and_clear_ready_role_removals(now).await,
+            )
+          };
 
-        let arc_clone = http_arc.clone();
+          let arc_clone = http_arc.clone();
 
-        spawn(async move {
-          match jobs {
-            Ok(tasks) => {
-              for job in tasks.iter() {
-                job.call(&arc_clone).await;
+          spawn(async move {
+            match jobs {
+              Ok(tasks) => {
+                for job in tasks.iter() {
+                  job.call(&arc_clone).await;
+                }
               }
-            }
-            Err(error) => println!("{:?}", error),
-          };
-        });
+              Err(error) => println!("{:?}", error),
+            };
+
+            match role_removals {
+              Ok(tasks) => {
+                for job in tasks.iter() {
+                  job.call(&arc_clone).await;
+                }
+              }
+              Err(error) => println!("{:?}", error),
+            };
+          });
+        }
 
         interval.tick().await;
       }
@@ -257,9 +409,9 @@ async fn main() {
 
     let http_clone = client.cache_and_http.http.clone();
 
-    let conn_key = Arc::new(Mutex::new(RedisWrapper(persistent_connection)));
     let conn_clone = conn_key.clone();
     let healthcheck = conn_key.clone();
+    let healthcheck_lease = lease.clone();
 
     let shards = client.shard_manager.clone();
 
@@ -301,8 +453,12 @@ async fn main() {
         file
           .write_all(
             format!(
-              "OK: {}\nRedis: {:?}\nDiscord: {} disconnected\nTime: {}",
-              ok, resp, disconnected, now
+              "OK: {}\nRedis: {:?}\nDiscord: {} disconnected\nLeader: {}\nTime: {}",
+              ok,
+              resp,
+              disconnected,
+              healthcheck_lease.is_leader(),
+              now
             )
             .as_bytes(),
           )
@@ -312,121 +468,62 @@ async fn main() {
       }
     });
 
-    spawn(async move {
-      loop {
-        let now = Utc::now().with_timezone(&EST5EDT);
-        let next_time = now
-          .add(ChronoDuration::days(1))
-          .with_hour(0)
-          .unwrap()
-          .with_minute(0)
-          .unwrap()
-          .with_second(30)
-          .unwrap();
-
-        let duration_to_sleep = next_time.signed_duration_since(now).to_std().unwrap();
+    let birthday_lease = lease.clone();
+    let birthday_scheduler = redis_scheduler_arc.clone();
 
-        sleep(duration_to_sleep).await;
+    // Both of these check every 30 seconds (rather than sleeping until the
+    // next announcement) so that anything missed while the bot was down, or
+    // while another instance held the lease, is picked up promptly
+    spawn(async move {
+      let mut interval: tokio::time::Interval = interval(Duration::from_secs(30));
 
-        let keys: Result<HashMap<String, String>, RedisError> =
-          conn_clone.lock().await.0.hgetall(BIRTHDAY_KEY).await;
+      loop {
+        interval.tick().await;
 
-        if let Err(error) = keys {
-          println!("Error getting birthdays: {:?}", error);
+        if !birthday_lease.is_leader() {
           continue;
         }
 
-        for (user_id, birthday) in keys.unwrap() {
-          let date = NaiveDate::parse_from_str(&birthday, BIRTHDAY_FMT).unwrap();
-
-          if date.month() == next_time.month() && date.day() == next_time.day() {
-            let age = next_time.year() - date.year();
+        if let Err(error) = catch_up_birthdays(
+          &conn_clone,
+          &birthday_scheduler,
+          &http_clone,
+          &birthday_lease,
+          birthday_hour,
+          Utc::now(),
+        )
+        .await
+        {
+          println!("{}", error);
+        }
 
-            let _ = ChannelId(birthday_announce_channel)
-              .say(
-                &http_clone,
-                format!("Happy birthday <@{}>. {} years", user_id, age),
-              )
-              .await;
-          }
+        if let Err(error) =
+          send_birthday_reminders(&conn_clone, &http_clone, birthday_hour, Utc::now()).await
+        {
+          println!("{}", error);
         }
       }
     });
 
     let http_clone2 = client.cache_and_http.http.clone();
     let conn_clone2 = conn_key.clone();
+    let briefing_lease = lease.clone();
 
     spawn(async move {
-      let mut now = Utc::now().with_timezone(&EST5EDT);
-
-      let mut this_weeks_monday = now
-        .sub(ChronoDuration::days(
-          now.weekday().num_days_from_monday().into(),
-        ))
-        .with_hour(7)
-        .unwrap()
-        .with_minute(30)
-        .unwrap();
-
-      let current_jobs: MyVec = {
-        let mut client = conn_clone2.lock().await;
-        client
-          .0
-          .zrangebyscore(BRIEFING_KEY, "-inf", this_weeks_monday.timestamp())
-          .await
-          .expect("Expected to be able to get keys")
-      };
-      if current_jobs.v.len() > 0 {
-        if let Err(error) =
-          send_briefing(&current_jobs, birthday_announce_channel, &http_clone2).await
-        {
-          println!("{}", error);
-        } else {
-          let _: u64 = {
-            let mut client = conn_clone2.lock().await;
-            client
-              .0
-              .zrembyscore(BRIEFING_KEY, "-inf", this_weeks_monday.timestamp())
-              .await
-              .expect("Expected to be able to remove old keys")
-          };
-        }
-      }
+      let mut interval: tokio::time::Interval = interval(Duration::from_secs(30));
 
       loop {
-        let next_time = this_weeks_monday.add(ChronoDuration::weeks(1));
-
-        let duration_to_sleep = next_time.signed_duration_since(now).to_std().unwrap();
-
-        sleep(duration_to_sleep).await;
+        interval.tick().await;
 
-        let current_jobs: MyVec = {
-          let mut client = conn_clone2.lock().await;
-          client
-            .0
-            .zrangebyscore(BRIEFING_KEY, "-inf", next_time.timestamp())
-            .await
-            .expect("Expected to be able to get keys")
-        };
-        if current_jobs.v.len() > 0 {
-          if let Err(error) =
-            send_briefing(&current_jobs, birthday_announce_channel, &http_clone2).await
-          {
-            println!("{}", error);
-          } else {
-            let _: u64 = {
-              let mut client = conn_clone2.lock().await;
-              client
-                .0
-                .zrembyscore(BRIEFING_KEY, "-inf", next_time.timestamp())
-                .await
-                .expect("Expected to be able to remove old keys")
-            };
-          }
+        if !briefing_lease.is_leader() {
+          continue;
         }
 
-        this_weeks_monday = next_time;
-        now = Utc::now().with_timezone(&EST5EDT);
+        if let Err(error) =
+          catch_up_briefings(&conn_clone2, &http_clone2, &briefing_lease, Utc::now()).await
+        {
+          println!("{}", error);
+        }
       }
     });
 
@@ -445,11 +542,13 @@ async fn main() {
       .expect("Expected to create guild commands");
 
     Command::set_global_application_commands(&http, |commands| {
-      birthday_command(copy_command(paste_command(sanitize_command(roll_command(
-        poll_command(owo_command(nya_command(news_command(help_command(
-          unshittify_command(commands),
+      birthday_command(starboard_command(quote_command(initiative_command(
+        copy_command(paste_command(sanitize_command(roll_command(poll_command(
+          owo_command(nya_command(news_command(help_command(timezone_command(
+            unshittify_command(commands),
+          ))))),
         ))))),
-      )))))
+      ))))
     })
     .await
     .expect("Expected to clear application commands");
@@ -0,0 +1,65 @@
+use lazy_static::lazy_static;
+use redis::{aio::Connection, RedisResult, Script, ToRedisArgs};
+
+lazy_static! {
+  static ref ADVANCE_FIELD_SCRIPT: Script = Script::new(
+    r"
+    local current = tonumber(redis.call('HGET', KEYS[1], ARGV[1]))
+    if current == nil or current < tonumber(ARGV[2]) then
+      redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+      return 1
+    end
+    return 0"
+  );
+  static ref RELEASE_FIELD_SCRIPT: Script = Script::new(
+    r"
+    if tonumber(redis.call('HGET', KEYS[1], ARGV[1])) ~= tonumber(ARGV[2]) then
+      return 0
+    end
+    if ARGV[3] == '' then
+      redis.call('HDEL', KEYS[1], ARGV[1])
+    else
+      redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+    end
+    return 1"
+  );
+}
+
+/// Atomically moves one field of a hash of checkpoints forward to `value`.
+/// Returns true if the checkpoint was moved (the caller now "owns" `value`), or
+/// false if the checkpoint was already at or past `value`.
+/// Claiming before doing the work means that work is done at most once
+pub async fn advance_field_checkpoint<F: ToRedisArgs>(
+  con: &mut Connection,
+  key: &str,
+  field: F,
+  value: i64,
+) -> RedisResult<bool> {
+  let advanced: i64 = ADVANCE_FIELD_SCRIPT
+    .key(key)
+    .arg(field)
+    .arg(value)
+    .invoke_async(con)
+    .await?;
+  Ok(advanced == 1)
+}
+
+/// Undoes `advance_field_checkpoint` when the claimed work couldn't be done,
+/// putting the field back to `previous` (or removing it). Nothing changes if
+/// the checkpoint has moved on from `value` since
+pub async fn release_field_checkpoint<F: ToRedisArgs>(
+  con: &mut Connection,
+  key: &str,
+  field: F,
+  value: i64,
+  previous: Option<i64>,
+) -> RedisResult<bool> {
+  let released: i64 = RELEASE_FIELD_SCRIPT
+    .key(key)
+    .arg(field)
+    .arg(value)
+    .arg(previous.map_or(String::new(), |previous| previous.to_string()))
+    .invoke_async(con)
+    .await?;
+  Ok(released == 1)
+}
@@ -0,0 +1,1502 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt,
+  ops::RangeInclusive,
+};
+
+use rand::Rng;
+
+/// The most dice a single expression can roll (not counting explosions)
+pub const MAX_DICE: u32 = 1000;
+
+/// How many times a single die can explode or be rerolled, so that a long
+/// streak still ends
+const MAX_CHAIN: usize = 100;
+
+/// How much work (roughly, how many multiplications) working out the odds of
+/// an expression can take
+const MAX_WORK: usize = 25_000_000;
+
+/// The most totals a distribution can have
+const MAX_TOTALS: usize = 100_000;
+
+/// Chances smaller than this are left out of distributions, so that
+/// exploding dice don't go on (almost) forever
+const NEGLIGIBLE: f64 = 1e-15;
+
+/// How many dice `pretty_vec` lists before summarizing the rest
+const MAX_SHOWN_DICE: usize = 100;
+
+/// Words that mean something in a roll, longest first so that `dl` is not
+/// read as `d` followed by `l`
+const WORDS: [&str; 10] = ["adv", "dis", "dl", "dh", "kh", "kl", "ro", "d", "r", "f"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+  Number(u32),
+  Word(&'static str),
+  Compare(Compare),
+  Bang,
+  Plus,
+  Minus,
+  Star,
+  Slash,
+  Open,
+  Close,
+}
+
+/// Why a roll could not be understood, and where
+#[derive(Debug)]
+pub struct ParseError {
+  /// Which character (not byte) the problem is at
+  pub position: usize,
+  pub message: String,
+  /// Whether the roll is garbled enough that it's worth guessing at.
+  /// Rolls that make sense but ask for something impossible (like dropping
+  /// every die) are just explained instead
+  pub guess: bool,
+}
+
+impl ParseError {
+  fn new(position: usize, message: &str) -> ParseError {
+    ParseError {
+      position,
+      message: String::from(message),
+      guess: true,
+    }
+  }
+
+  /// An error for a roll that makes sense, but can't be done
+  fn impossible(position: usize, message: String) -> ParseError {
+    ParseError {
+      position,
+      message,
+      guess: false,
+    }
+  }
+
+  /// Shows the message, with the roll and an arrow pointing at the problem
+  pub fn render(&self, input: &str) -> String {
+    format!(
+      "**{}**\n```\n{}\n{}^\n```",
+      self.message,
+      input,
+      " ".repeat(self.position)
+    )
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+}
+
+impl fmt::Display for Op {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let symbol = match self {
+      Op::Add => "+",
+      Op::Subtract => "-",
+      Op::Multiply => "*",
+      Op::Divide => "/",
+    };
+
+    write!(f, "{}", symbol)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+  Equal,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+/// A test for a single die, like `>=7`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+  pub compare: Compare,
+  pub value: i64,
+}
+
+impl Condition {
+  pub fn matches(&self, value: i64) -> bool {
+    match self.compare {
+      Compare::Equal => value == self.value,
+      Compare::Less => value < self.value,
+      Compare::LessOrEqual => value <= self.value,
+      Compare::Greater => value > self.value,
+      Compare::GreaterOrEqual => value >= self.value,
+    }
+  }
+}
+
+impl fmt::Display for Condition {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let symbol = match self.compare {
+      Compare::Equal => "",
+      Compare::Less => "<",
+      Compare::LessOrEqual => "<=",
+      Compare::Greater => ">",
+      Compare::GreaterOrEqual => ">=",
+    };
+
+    write!(f, "{}{}", symbol, self.value)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sides {
+  Numbered(u32),
+  /// Fudge (Fate) dice, which land on -1, 0 or +1
+  Fudge,
+}
+
+impl Sides {
+  /// The values the die can land on
+  pub fn faces(&self) -> RangeInclusive<i64> {
+    match self {
+      Sides::Numbered(sides) => 1..=(*sides).into(),
+      Sides::Fudge => -1..=1,
+    }
+  }
+}
+
+impl fmt::Display for Sides {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Sides::Numbered(sides) => write!(f, "{}", sides),
+      Sides::Fudge => write!(f, "F"),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reroll {
+  pub condition: Condition,
+  /// Only reroll once (`ro`), rather than until it stops matching (`r`)
+  pub once: bool,
+}
+
+/// Which dice to keep, like `kh3`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keep {
+  pub highest: bool,
+  pub amount: u32,
+}
+
+/// A set of identical dice, like `4d6dl1`, `4d6kh3` or `10d10>=7`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiceTerm {
+  pub count: u32,
+  pub sides: Sides,
+  pub drop_low: u32,
+  pub drop_high: u32,
+  /// Keep only some of the dice. Can't be used together with dropping
+  pub keep: Option<Keep>,
+  /// Roll another die whenever one matches this
+  pub explode: Option<Condition>,
+  pub reroll: Option<Reroll>,
+  /// Count the kept dice that match this, rather than adding them up
+  pub success: Option<Condition>,
+}
+
+impl DiceTerm {
+  fn new(count: u32, sides: Sides) -> DiceTerm {
+    DiceTerm {
+      count,
+      sides,
+      drop_low: 0,
+      drop_high: 0,
+      keep: None,
+      explode: None,
+      reroll: None,
+      success: None,
+    }
+  }
+
+  /// What a bare `!` explodes on: the highest face
+  fn default_explode(&self) -> Condition {
+    Condition {
+      compare: Compare::Equal,
+      value: *self.sides.faces().end(),
+    }
+  }
+
+  /// Whether every face matches `condition`, so it would never stop
+  fn always(&self, condition: &Condition) -> bool {
+    self.sides.faces().all(|face| condition.matches(face))
+  }
+}
+
+impl fmt::Display for DiceTerm {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}d{}", self.count, self.sides)?;
+
+    if let Some(explode) = &self.explode {
+      if *explode == self.default_explode() {
+        write!(f, "!")?;
+      } else {
+        write!(f, "!{}", explode)?;
+      }
+    }
+
+    if let Some(reroll) = &self.reroll {
+      let word = if reroll.once { "ro" } else { "r" };
+      write!(f, "{}{}", word, reroll.condition)?;
+    }
+
+    if self.drop_low > 0 {
+      write!(f, "dl{}", self.drop_low)?;
+    }
+
+    if self.drop_high > 0 {
+      write!(f, "dh{}", self.drop_high)?;
+    }
+
+    if let Some(keep) = &self.keep {
+      let word = if keep.highest { "kh" } else { "kl" };
+      write!(f, "{}{}", word, keep.amount)?;
+    }
+
+    if let Some(success) = &self.success {
+      write!(f, "{}", success)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// A parsed roll
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+  Number(i64),
+  Dice(DiceTerm),
+  Negate(Box<Expr>),
+  Binary(Box<Expr>, Op, Box<Expr>),
+  /// Something in parentheses. Only kept around so it can be shown again
+  Group(Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Expr::Number(number) => write!(f, "{}", number),
+      Expr::Dice(dice) => write!(f, "{}", dice),
+      Expr::Negate(inner) => write!(f, "-{}", inner),
+      Expr::Binary(left, op, right) => write!(f, "{} {} {}", left, op, right),
+      Expr::Group(inner) => write!(f, "({})", inner),
+    }
+  }
+}
+
+/// One die that was rolled
+#[derive(Clone, Debug)]
+pub struct Die {
+  pub value: i64,
+  /// What it landed on before being rerolled, oldest first
+  pub rerolled: Vec<i64>,
+  /// Whether this die caused another to be rolled
+  pub exploded: bool,
+  pub dropped: bool,
+  pub success: bool,
+}
+
+impl Die {
+  fn new(value: i64) -> Die {
+    Die {
+      value,
+      rerolled: vec![],
+      exploded: false,
+      dropped: false,
+      success: false,
+    }
+  }
+}
+
+/// The result of rolling an expression
+pub struct Roll {
+  pub total: i64,
+  /// The expression, with every die that was rolled shown
+  pub breakdown: String,
+  /// How many kept d20s landed on 20
+  pub natural_20s: u32,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+  let chars: Vec<char> = input.chars().collect();
+  let mut tokens: Vec<(usize, Token)> = vec![];
+  let mut idx = 0;
+
+  while idx < chars.len() {
+    let token = match chars[idx] {
+      c if c.is_whitespace() => {
+        idx += 1;
+        continue;
+      }
+      '0'..='9' => {
+        let start = idx;
+
+        while idx < chars.len() && chars[idx].is_ascii_digit() {
+          idx += 1;
+        }
+
+        let digits: String = chars[start..idx].iter().collect();
+
+        match digits.parse::<u32>() {
+          Ok(number) => tokens.push((start, Token::Number(number))),
+          Err(_) => return Err(ParseError::new(start, "This number is too big")),
+        }
+
+        continue;
+      }
+      c @ ('<' | '>') => {
+        let or_equal = chars.get(idx + 1) == Some(&'=');
+
+        let compare = match (c, or_equal) {
+          ('<', false) => Compare::Less,
+          ('<', true) => Compare::LessOrEqual,
+          ('>', false) => Compare::Greater,
+          _ => Compare::GreaterOrEqual,
+        };
+
+        tokens.push((idx, Token::Compare(compare)));
+        idx += if or_equal { 2 } else { 1 };
+        continue;
+      }
+      '=' => Token::Compare(Compare::Equal),
+      '!' => Token::Bang,
+      '+' => Token::Plus,
+      '-' => Token::Minus,
+      '*' => Token::Star,
+      '/' => Token::Slash,
+      '(' => Token::Open,
+      ')' => Token::Close,
+      c => {
+        let word = WORDS.iter().find(|word| {
+          word.chars().enumerate().all(|(offset, letter)| {
+            chars
+              .get(idx + offset)
+              .map_or(false, |c| c.eq_ignore_ascii_case(&letter))
+          })
+        });
+
+        match word {
+          Some(word) => {
+            tokens.push((idx, Token::Word(word)));
+            idx += word.len();
+            continue;
+          }
+          None => {
+            return Err(ParseError::new(
+              idx,
+              &format!("I don't know what '{}' means here", c),
+            ))
+          }
+        }
+      }
+    };
+
+    tokens.push((idx, token));
+    idx += 1;
+  }
+
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<(usize, Token)>,
+  index: usize,
+  /// Where the input ends, for pointing at missing things
+  end: usize,
+  /// How many dice have been read so far
+  dice_count: u32,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<Token> {
+    self.tokens.get(self.index).map(|(_, token)| *token)
+  }
+
+  fn position(&self) -> usize {
+    self
+      .tokens
+      .get(self.index)
+      .map_or(self.end, |(position, _)| *position)
+  }
+
+  fn advance(&mut self) {
+    self.index += 1;
+  }
+
+  /// A sum or difference of terms
+  fn expression(&mut self) -> Result<Expr, ParseError> {
+    let mut left = self.term()?;
+
+    loop {
+      let op = match self.peek() {
+        Some(Token::Plus) => Op::Add,
+        Some(Token::Minus) => Op::Subtract,
+        _ => return Ok(left),
+      };
+
+      self.advance();
+      let right = self.term()?;
+      left = Expr::Binary(Box::new(left), op, Box::new(right));
+    }
+  }
+
+  /// A product or quotient of factors
+  fn term(&mut self) -> Result<Expr, ParseError> {
+    let mut left = self.factor()?;
+
+    loop {
+      let op = match self.peek() {
+        Some(Token::Star) => Op::Multiply,
+        Some(Token::Slash) => Op::Divide,
+        _ => return Ok(left),
+      };
+
+      self.advance();
+      let right = self.factor()?;
+      left = Expr::Binary(Box::new(left), op, Box::new(right));
+    }
+  }
+
+  fn factor(&mut self) -> Result<Expr, ParseError> {
+    let position = self.position();
+
+    match self.peek() {
+      Some(Token::Minus) => {
+        self.advance();
+        Ok(Expr::Negate(Box::new(self.factor()?)))
+      }
+      Some(Token::Number(number)) => {
+        self.advance();
+
+        if self.peek() == Some(Token::Word("d")) {
+          self.dice(number, position)
+        } else {
+          Ok(Expr::Number(number.into()))
+        }
+      }
+      Some(Token::Word("d")) => self.dice(1, position),
+      // D&D advantage and disadvantage: roll 2d20 and keep the better (or worse)
+      Some(Token::Word(word @ ("adv" | "dis"))) => {
+        self.advance();
+
+        let mut term = DiceTerm::new(2, Sides::Numbered(20));
+        term.keep = Some(Keep {
+          highest: word == "adv",
+          amount: 1,
+        });
+
+        self.count_dice(term.count, position)?;
+        Ok(Expr::Dice(term))
+      }
+      Some(Token::Open) => {
+        self.advance();
+        let inner = self.expression()?;
+
+        if self.peek() != Some(Token::Close) {
+          return Err(ParseError::new(
+            self.position(),
+            "Expected a ')' to match the '(' before this",
+          ));
+        }
+
+        self.advance();
+        Ok(Expr::Group(Box::new(inner)))
+      }
+      Some(_) => Err(ParseError::new(
+        position,
+        "Expected a number, dice or '(' here",
+      )),
+      None => Err(ParseError::new(
+        position,
+        "The roll ends too soon. Expected a number, dice or '('",
+      )),
+    }
+  }
+
+  /// A condition like `>=7`, or just `3` (meaning exactly 3)
+  fn condition(&mut self) -> Result<Condition, ParseError> {
+    let compare = match self.peek() {
+      Some(Token::Compare(compare)) => {
+        self.advance();
+        compare
+      }
+      _ => Compare::Equal,
+    };
+
+    match self.peek() {
+      Some(Token::Number(value)) => {
+        self.advance();
+
+        Ok(Condition {
+          compare,
+          value: value.into(),
+        })
+      }
+      _ => Err(ParseError::new(
+        self.position(),
+        "Expected a number to compare the dice to",
+      )),
+    }
+  }
+
+  /// Dice, starting at the `d` (the count, if any, has already been read)
+  fn dice(&mut self, count: u32, start: usize) -> Result<Expr, ParseError> {
+    self.advance();
+
+    let sides = match self.peek() {
+      Some(Token::Number(sides)) => Sides::Numbered(sides),
+      Some(Token::Word("f")) => Sides::Fudge,
+      _ => {
+        return Err(ParseError::new(
+          self.position(),
+          "Expected the number of sides on the dice, or F for fudge dice",
+        ))
+      }
+    };
+
+    if count == 0 {
+      return Err(ParseError::new(
+        start,
+        "I *can* roll zero dice, but am morally obligated not to",
+      ));
+    }
+
+    if sides == Sides::Numbered(0) {
+      return Err(ParseError::new(self.position(), "I will not roll a d0"));
+    }
+
+    self.advance();
+
+    let mut term = DiceTerm::new(count, sides);
+
+    // Where the last keep or drop was, to point at if it can't be done
+    let mut selection_start = start;
+
+    loop {
+      let modifier_start = self.position();
+
+      match self.peek() {
+        Some(Token::Word(word @ ("dl" | "dh" | "kh" | "kl"))) => {
+          self.advance();
+
+          // `dl` or `kh` on its own drops or keeps one die
+          let amount = match self.peek() {
+            Some(Token::Number(number)) => {
+              self.advance();
+              number
+            }
+            _ => 1,
+          };
+
+          let keeping = word.starts_with('k');
+
+          if term.keep.is_some() || (keeping && term.drop_low + term.drop_high > 0) {
+            return Err(ParseError::impossible(
+              modifier_start,
+              String::from("Dice can be kept (kh/kl) or dropped (dl/dh), but not both at once"),
+            ));
+          }
+
+          // Checked here too, since adding huge amounts up could overflow
+          if amount > count {
+            return Err(ParseError::impossible(
+              modifier_start,
+              if keeping {
+                format!(
+                  "You want to keep {} dice but are only rolling {}",
+                  amount, count
+                )
+              } else {
+                drop_message(u64::from(amount), count)
+              },
+            ));
+          }
+
+          match word {
+            "dl" | "dh" => {
+              let side = if word == "dl" {
+                &mut term.drop_low
+              } else {
+                &mut term.drop_high
+              };
+
+              *side = side.checked_add(amount).ok_or_else(|| {
+                ParseError::impossible(
+                  modifier_start,
+                  drop_message(u64::from(*side) + u64::from(amount), count),
+                )
+              })?;
+            }
+            _ => {
+              term.keep = Some(Keep {
+                highest: word == "kh",
+                amount,
+              })
+            }
+          }
+
+          selection_start = modifier_start;
+        }
+        Some(Token::Word(word @ ("r" | "ro"))) => {
+          self.advance();
+
+          let reroll = Reroll {
+            condition: self.condition()?,
+            once: word == "ro",
+          };
+
+          if !reroll.once && term.always(&reroll.condition) {
+            return Err(ParseError::new(
+              modifier_start,
+              "Every roll matches this, so I'd be rerolling forever",
+            ));
+          }
+
+          term.reroll = Some(reroll);
+        }
+        Some(Token::Bang) => {
+          self.advance();
+
+          // `!` on its own explodes on the highest face
+          let explode = match self.peek() {
+            Some(Token::Compare(_)) | Some(Token::Number(_)) => self.condition()?,
+            _ => term.default_explode(),
+          };
+
+          if term.always(&explode) {
+            return Err(ParseError::new(
+              modifier_start,
+              "Every roll would explode, so these dice would never stop",
+            ));
+          }
+
+          term.explode = Some(explode);
+        }
+        Some(Token::Compare(_)) => term.success = Some(self.condition()?),
+        _ => break,
+      }
+    }
+
+    let dropped = u64::from(term.drop_low) + u64::from(term.drop_high);
+
+    if dropped >= u64::from(count) {
+      return Err(ParseError::impossible(
+        selection_start,
+        drop_message(dropped, count),
+      ));
+    }
+
+    if let Some(keep) = &term.keep {
+      if keep.amount == 0 {
+        return Err(ParseError::impossible(
+          selection_start,
+          String::from("Keeping 0 dice leaves nothing to roll. You need to keep at least 1"),
+        ));
+      }
+    }
+
+    self.count_dice(count, start)?;
+    Ok(Expr::Dice(term))
+  }
+
+  /// Keeps track of how many dice have been read, so there aren't too many
+  fn count_dice(&mut self, count: u32, start: usize) -> Result<(), ParseError> {
+    // Checked before adding, since a huge count could overflow the total
+    if count > MAX_DICE - self.dice_count {
+      return Err(ParseError::new(
+        start,
+        &format!("I can only roll {} dice at once", MAX_DICE),
+      ));
+    }
+
+    self.dice_count += count;
+    Ok(())
+  }
+}
+
+/// Explains why `dropped` of `count` dice can't be dropped
+fn drop_message(dropped: u64, count: u32) -> String {
+  format!(
+    "You want to drop {} of {} dice, which leaves nothing to roll. You can drop at most {}",
+    dropped,
+    count,
+    count - 1
+  )
+}
+
+/// Parses a roll like `2d6+1d4+3`, `(1d8+2)*2`, `4d6kh3`, `adv+5`, `4d6!`,
+/// `2d20ro<3`, `10d10>=7` or `4dF`
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+  let tokens = tokenize(input)?;
+
+  let mut parser = Parser {
+    tokens,
+    index: 0,
+    end: input.chars().count(),
+    dice_count: 0,
+  };
+
+  let expr = parser.expression()?;
+
+  match parser.peek() {
+    None => Ok(expr),
+    Some(Token::Close) => Err(ParseError::new(
+      parser.position(),
+      "This ')' doesn't have a '(' to match",
+    )),
+    Some(_) => Err(ParseError::new(
+      parser.position(),
+      "I don't know what to do with this. Did you forget a + or -?",
+    )),
+  }
+}
+
+impl DiceTerm {
+  /// Rolls a single die, rerolling it as many times as it needs
+  fn roll_die<R: Rng>(&self, rng: &mut R) -> Die {
+    let mut die = Die::new(rng.gen_range(self.sides.faces()));
+
+    if let Some(reroll) = &self.reroll {
+      while reroll.condition.matches(die.value) && die.rerolled.len() < MAX_CHAIN {
+        die.rerolled.push(die.value);
+        die.value = rng.gen_range(self.sides.faces());
+
+        if reroll.once {
+          break;
+        }
+      }
+    }
+
+    die
+  }
+
+  /// Rolls the dice, sorted from lowest to highest
+  fn roll<R: Rng>(&self, rng: &mut R) -> Vec<Die> {
+    let mut dice: Vec<Die> = vec![];
+
+    for _ in 0..self.count {
+      let mut die = self.roll_die(rng);
+
+      // Every explosion rolls another die, which can explode too
+      if let Some(explode) = &self.explode {
+        let mut explosions = 0;
+
+        while explode.matches(die.value) && explosions < MAX_CHAIN {
+          die.exploded = true;
+          dice.push(die);
+          die = self.roll_die(rng);
+          explosions += 1;
+        }
+      }
+
+      dice.push(die);
+    }
+
+    dice.sort_by_key(|die| die.value);
+
+    let mut kept = (self.drop_low as usize)..(dice.len() - self.drop_high as usize);
+
+    if let Some(keep) = &self.keep {
+      let amount = (keep.amount as usize).min(dice.len());
+
+      if keep.highest {
+        kept.start = dice.len() - amount;
+      } else {
+        kept.end = amount;
+      }
+    }
+
+    for (idx, die) in dice.iter_mut().enumerate() {
+      die.dropped = !kept.contains(&idx);
+      die.success = !die.dropped
+        && self
+          .success
+          .map_or(false, |success| success.matches(die.value));
+    }
+
+    dice
+  }
+}
+
+/// Divides, rounding down (rather than towards zero)
+fn divide(left: i64, right: i64) -> Option<i64> {
+  let quotient = left.checked_div(right)?;
+
+  if left % right != 0 && (left < 0) != (right < 0) {
+    Some(quotient - 1)
+  } else {
+    Some(quotient)
+  }
+}
+
+impl Expr {
+  /// Rolls every die in the expression and works out the total
+  pub fn roll<R: Rng>(&self, rng: &mut R) -> Result<Roll, String> {
+    match self {
+      Expr::Number(number) => Ok(Roll {
+        total: *number,
+        breakdown: number.to_string(),
+        natural_20s: 0,
+      }),
+      Expr::Dice(term) => {
+        let dice = term.roll(rng);
+        let fudge = term.sides == Sides::Fudge;
+
+        let (total, breakdown) = match term.success {
+          Some(_) => {
+            let successes = dice.iter().filter(|die| die.success).count() as i64;
+
+            (
+              successes,
+              format!(
+                "{} [{}] ({} successes)",
+                term,
+                pretty_vec(&dice, fudge),
+                successes
+              ),
+            )
+          }
+          None => (
+            dice
+              .iter()
+              .filter(|die| !die.dropped)
+              .map(|die| die.value)
+              .sum(),
+            format!("{} [{}]", term, pretty_vec(&dice, fudge)),
+          ),
+        };
+
+        let natural_20s = match term.sides {
+          Sides::Numbered(20) => dice
+            .iter()
+            .filter(|die| !die.dropped && die.value == 20)
+            .count() as u32,
+          _ => 0,
+        };
+
+        Ok(Roll {
+          total,
+          breakdown,
+          natural_20s,
+        })
+      }
+      Expr::Negate(inner) => {
+        let inner = inner.roll(rng)?;
+
+        Ok(Roll {
+          total: inner.total.checked_neg().ok_or_else(too_big)?,
+          breakdown: format!("-{}", inner.breakdown),
+          natural_20s: inner.natural_20s,
+        })
+      }
+      Expr::Binary(left, op, right) => {
+        let left = left.roll(rng)?;
+        let right = right.roll(rng)?;
+
+        let total = match op {
+          Op::Add => left.total.checked_add(right.total),
+          Op::Subtract => left.total.checked_sub(right.total),
+          Op::Multiply => left.total.checked_mul(right.total),
+          Op::Divide => {
+            if right.total == 0 {
+              return Err(String::from("I can't divide by zero"));
+            }
+
+            divide(left.total, right.total)
+          }
+        };
+
+        Ok(Roll {
+          total: total.ok_or_else(too_big)?,
+          breakdown: format!("{} {} {}", left.breakdown, op, right.breakdown),
+          natural_20s: left.natural_20s + right.natural_20s,
+        })
+      }
+      Expr::Group(inner) => {
+        let inner = inner.roll(rng)?;
+
+        Ok(Roll {
+          total: inner.total,
+          breakdown: format!("({})", inner.breakdown),
+          natural_20s: inner.natural_20s,
+        })
+      }
+    }
+  }
+}
+
+/// Shows the face a die landed on, with + and - for fudge dice
+fn face(value: i64, fudge: bool) -> String {
+  match (fudge, value) {
+    (true, 1) => String::from("+"),
+    (true, -1) => String::from("-"),
+    _ => value.to_string(),
+  }
+}
+
+/// Creates a String representation of `dice`, separated by commas.
+/// Rerolled dice show what they were first (`1→4`), dice that exploded are
+/// followed by `!`, successes are bold and dropped dice are struck out
+/// # Arguments
+/// - `dice` - the dice that were rolled
+/// - `fudge` - whether they are fudge dice
+pub fn pretty_vec(dice: &[Die], fudge: bool) -> String {
+  let mut string_list: Vec<String> = dice
+    .iter()
+    .take(MAX_SHOWN_DICE)
+    .map(|die| {
+      let mut shown = String::new();
+
+      for old in &die.rerolled {
+        shown += &format!("{}→", face(*old, fudge));
+      }
+
+      shown += &face(die.value, fudge);
+
+      if die.exploded {
+        shown += "!";
+      }
+
+      if die.success {
+        format!("**{}**", shown)
+      } else if die.dropped {
+        format!("~~{}~~", shown)
+      } else {
+        shown
+      }
+    })
+    .collect();
+
+  if dice.len() > MAX_SHOWN_DICE {
+    string_list.push(format!("and {} more", dice.len() - MAX_SHOWN_DICE));
+  }
+
+  string_list.join(", ")
+}
+
+/// The chance of rolling each total, from `Expr::distribution`. Some
+/// chances are so small they are 0 as an `f64`, but every total that can be
+/// rolled is still here, except with exploding dice
+#[derive(Clone, Debug)]
+pub struct Distribution {
+  pub chances: BTreeMap<i64, f64>,
+  /// Whether totals rarer than `NEGLIGIBLE` were left out, which only
+  /// happens with exploding dice
+  pub open_ended: bool,
+}
+
+impl Distribution {
+  fn constant(value: i64) -> Distribution {
+    Distribution {
+      chances: BTreeMap::from([(value, 1.0)]),
+      open_ended: false,
+    }
+  }
+
+  fn empty() -> Distribution {
+    Distribution {
+      chances: BTreeMap::new(),
+      open_ended: false,
+    }
+  }
+
+  fn add_chance(&mut self, value: i64, chance: f64) {
+    *self.chances.entry(value).or_insert(0.0) += chance;
+  }
+
+  pub fn mean(&self) -> f64 {
+    self
+      .chances
+      .iter()
+      .map(|(value, chance)| *value as f64 * chance)
+      .sum()
+  }
+
+  pub fn std_dev(&self) -> f64 {
+    let mean = self.mean();
+
+    self
+      .chances
+      .iter()
+      .map(|(value, chance)| (*value as f64 - mean).powi(2) * chance)
+      .sum::<f64>()
+      .sqrt()
+  }
+
+  pub fn min(&self) -> i64 {
+    self.chances.keys().next().copied().unwrap_or_default()
+  }
+
+  pub fn max(&self) -> i64 {
+    self.chances.keys().next_back().copied().unwrap_or_default()
+  }
+
+  /// The chance of rolling `target` or more
+  pub fn at_least(&self, target: i64) -> f64 {
+    self.chances.range(target..).map(|(_, chance)| chance).sum()
+  }
+
+  /// The distribution of `combine`ing a roll from `self` with one from `other`
+  fn combine<F: Fn(i64, i64) -> Option<i64>>(
+    &self,
+    other: &Distribution,
+    work: &mut usize,
+    combine: F,
+  ) -> Result<Distribution, String> {
+    spend(work, pairs(self, other)?)?;
+
+    let mut result = Distribution::empty();
+    result.open_ended = self.open_ended || other.open_ended;
+
+    for (left, left_chance) in &self.chances {
+      for (right, right_chance) in &other.chances {
+        let value = combine(*left, *right).ok_or_else(too_big)?;
+        result.add_chance(value, left_chance * right_chance);
+      }
+    }
+
+    if result.chances.len() > MAX_TOTALS {
+      return Err(too_many());
+    }
+
+    Ok(result)
+  }
+
+  /// Like `combine`ing with addition, but quicker
+  fn sum(&self, other: &Distribution, work: &mut usize) -> Result<Distribution, String> {
+    let low = self.min().checked_add(other.min()).ok_or_else(too_big)?;
+    let high = self.max().checked_add(other.max()).ok_or_else(too_big)?;
+
+    // Use a `Vec` when the totals are close together, which is much quicker
+    let span = match high.checked_sub(low) {
+      Some(span) if span < MAX_TOTALS as i64 => span as usize + 1,
+      _ => return self.combine(other, work, i64::checked_add),
+    };
+
+    spend(work, pairs(self, other)?.saturating_add(span))?;
+
+    // `None` for totals that can't be rolled at all
+    let mut totals: Vec<Option<f64>> = vec![None; span];
+
+    for (left, left_chance) in &self.chances {
+      let offset = (left - self.min()) as usize;
+
+      for (right, right_chance) in &other.chances {
+        let total = &mut totals[offset + (right - other.min()) as usize];
+        *total = Some(total.unwrap_or_default() + left_chance * right_chance);
+      }
+    }
+
+    Ok(Distribution {
+      chances: totals
+        .into_iter()
+        .enumerate()
+        .filter_map(|(idx, chance)| chance.map(|chance| (low + idx as i64, chance)))
+        .collect(),
+      open_ended: self.open_ended || other.open_ended,
+    })
+  }
+
+  fn prune(mut self) -> Distribution {
+    let before = self.chances.len();
+    self.chances.retain(|_, chance| *chance >= NEGLIGIBLE);
+    self.open_ended |= self.chances.len() < before;
+    self
+  }
+}
+
+/// How many pairs of totals there are to combine
+fn pairs(left: &Distribution, right: &Distribution) -> Result<usize, String> {
+  left
+    .chances
+    .len()
+    .checked_mul(right.chances.len())
+    .ok_or_else(too_many)
+}
+
+fn too_big() -> String {
+  String::from("That number is too big for me")
+}
+
+/// Uses up some of the work that working out odds is allowed
+fn spend(work: &mut usize, amount: usize) -> Result<(), String> {
+  *work = work.checked_sub(amount).ok_or_else(too_many)?;
+
+  Ok(())
+}
+
+fn too_many() -> String {
+  String::from("There are too many possible rolls for me to work out the odds exactly")
+}
+
+impl DiceTerm {
+  /// What a die landing on `value` adds to the total: either the value, or
+  /// 1 if it's a success
+  fn score(&self, value: i64) -> i64 {
+    match &self.success {
+      Some(success) => success.matches(value).into(),
+      None => value,
+    }
+  }
+
+  /// The chance of a single die landing on each face, after any rerolls
+  fn face_chances(&self, work: &mut usize) -> Result<Vec<(i64, f64)>, String> {
+    let faces = self.sides.faces();
+    let count = (faces.end() - faces.start() + 1) as usize;
+
+    if count > MAX_TOTALS {
+      return Err(too_many());
+    }
+
+    spend(work, count)?;
+
+    let chance = 1.0 / count as f64;
+
+    let reroll = match &self.reroll {
+      Some(reroll) => reroll,
+      None => return Ok(faces.map(|face| (face, chance)).collect()),
+    };
+
+    let matching = faces
+      .clone()
+      .filter(|face| reroll.condition.matches(*face))
+      .count() as f64
+      * chance;
+    let tries = if reroll.once { 1 } else { MAX_CHAIN as i32 };
+
+    // A face that isn't rerolled can come up on any try, as long as every try
+    // before it was rerolled. One that is rerolled only stays on the last try
+    let any_try: f64 = (0..=tries).map(|tried| matching.powi(tried)).sum();
+    let last_try = matching.powi(tries);
+
+    Ok(
+      faces
+        .map(|face| {
+          if reroll.condition.matches(face) {
+            (face, chance * last_try)
+          } else {
+            (face, chance * any_try)
+          }
+        })
+        .collect(),
+    )
+  }
+
+  /// The chance of each score a single die adds, counting any dice it
+  /// explodes into
+  fn die_distribution(
+    &self,
+    faces: &[(i64, f64)],
+    work: &mut usize,
+  ) -> Result<Distribution, String> {
+    let mut single = Distribution::empty();
+    let mut stops = Distribution::empty();
+    let mut explodes = Distribution::empty();
+
+    for (face, chance) in faces {
+      single.add_chance(self.score(*face), *chance);
+
+      match &self.explode {
+        Some(explode) if explode.matches(*face) => explodes.add_chance(self.score(*face), *chance),
+        _ => stops.add_chance(self.score(*face), *chance),
+      }
+    }
+
+    if self.explode.is_none() {
+      return Ok(single);
+    }
+
+    // After exploding `MAX_CHAIN` times, a die just stops. Work backwards from
+    // there, until the chance of getting that far is negligible
+    let explode_chance: f64 = explodes.chances.values().sum();
+    let mut reached = 1.0;
+    let mut chain = single;
+
+    for _ in 0..MAX_CHAIN {
+      let mut next = explodes.sum(&chain, work)?;
+
+      for (score, chance) in &stops.chances {
+        next.add_chance(*score, *chance);
+      }
+
+      chain = next.prune();
+      reached *= explode_chance;
+
+      if reached < NEGLIGIBLE {
+        break;
+      }
+    }
+
+    Ok(chain)
+  }
+
+  /// The chance of each total these dice can roll
+  fn distribution(&self, work: &mut usize) -> Result<Distribution, String> {
+    let faces = self.face_chances(work)?;
+    let count = self.count as usize;
+
+    let selects = self.keep.is_some() || self.drop_low + self.drop_high > 0;
+
+    if !selects {
+      let die = self.die_distribution(&faces, work)?;
+      let mut total = die.clone();
+
+      for _ in 1..count {
+        total = total.sum(&die, work)?;
+      }
+
+      return Ok(total);
+    }
+
+    if self.explode.is_some() {
+      return Err(String::from(
+        "I can't work out the odds of exploding dice that are also kept or dropped",
+      ));
+    }
+
+    // Which dice (lowest first) are kept
+    let (low, high) = match &self.keep {
+      Some(keep) if keep.highest => (count - (keep.amount as usize).min(count), count),
+      Some(keep) => (0, (keep.amount as usize).min(count)),
+      None => (self.drop_low as usize, count - self.drop_high as usize),
+    };
+
+    // Go through the faces from lowest to highest, deciding how many dice
+    // land on each. Since they are in order, the dice that land on a face
+    // take up the next places, so it's known which of them are kept.
+    // `placed[n]` has the chance of each kept total, with `n` dice placed so far
+    let mut placed: Vec<HashMap<i64, f64>> = vec![HashMap::new(); count + 1];
+    placed[0].insert(0, 1.0);
+
+    for (face, chance) in faces {
+      let score = self.score(face);
+      let mut next: Vec<HashMap<i64, f64>> = vec![HashMap::new(); count + 1];
+
+      for (done, totals) in placed.iter().enumerate() {
+        if totals.is_empty() {
+          continue;
+        }
+
+        let left = count - done;
+        spend(work, totals.len().saturating_mul(left + 1))?;
+
+        // The chance of exactly `landed` of the `left` dice landing here,
+        // without the chance of the rest landing elsewhere (which is worked
+        // out by the later faces)
+        let mut ways = 1.0;
+
+        for landed in 0..=left {
+          if landed > 0 {
+            ways *= (left - landed + 1) as f64 / landed as f64 * chance;
+          }
+
+          let kept = (done + landed).min(high).saturating_sub(done.max(low)) as i64;
+
+          for (total, total_chance) in totals {
+            let total = kept
+              .checked_mul(score)
+              .and_then(|added| total.checked_add(added))
+              .ok_or_else(too_big)?;
+
+            *next[done + landed].entry(total).or_insert(0.0) += total_chance * ways;
+          }
+        }
+      }
+
+      placed = next;
+    }
+
+    let mut total = Distribution::empty();
+
+    for (value, chance) in placed.swap_remove(count) {
+      total.add_chance(value, chance);
+    }
+
+    Ok(total)
+  }
+}
+
+impl Expr {
+  /// Works out the exact chance of each total this expression can roll
+  pub fn distribution(&self) -> Result<Distribution, String> {
+    let mut work = MAX_WORK;
+    self.distribution_within(&mut work)
+  }
+
+  fn distribution_within(&self, work: &mut usize) -> Result<Distribution, String> {
+    match self {
+      Expr::Number(number) => Ok(Distribution::constant(*number)),
+      Expr::Dice(term) => term.distribution(work),
+      Expr::Negate(inner) => {
+        let inner = inner.distribution_within(work)?;
+        inner.combine(&Distribution::constant(0), work, |value, _| {
+          value.checked_neg()
+        })
+      }
+      Expr::Binary(left, op, right) => {
+        let left = left.distribution_within(work)?;
+        let right = right.distribution_within(work)?;
+
+        match op {
+          Op::Add => left.sum(&right, work),
+          Op::Subtract => left.combine(&right, work, i64::checked_sub),
+          Op::Multiply => left.combine(&right, work, i64::checked_mul),
+          Op::Divide => {
+            if right.chances.contains_key(&0) {
+              return Err(String::from(
+                "This might divide by zero, so I can't work out the odds",
+              ));
+            }
+
+            left.combine(&right, work, divide)
+          }
+        }
+      }
+      Expr::Group(inner) => inner.distribution_within(work),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_error(input: &str) -> ParseError {
+    match parse(input) {
+      Ok(_) => panic!("Expected {} not to parse", input),
+      Err(error) => error,
+    }
+  }
+
+  #[test]
+  fn rejects_dice_counts_that_would_overflow() {
+    let error = parse_error("1d6+4294967295d6");
+
+    assert_eq!(error.position, 4);
+    assert_eq!(error.message, "I can only roll 1000 dice at once");
+  }
+
+  #[test]
+  fn counts_dice_across_terms() {
+    assert!(parse("500d6+500d6").is_ok());
+    assert_eq!(parse_error("500d6+501d6").position, 6);
+  }
+
+  #[test]
+  fn rejects_drops_that_would_overflow() {
+    let error = parse_error("2d6dl2147483648dh2147483648");
+
+    assert_eq!(error.position, 3);
+    assert!(!error.guess);
+    assert!(parse_error("2d6dl1dh1")
+      .message
+      .starts_with("You want to drop 2 of 2 dice"));
+    assert!(parse_error("2d6kh3!")
+      .message
+      .starts_with("You want to keep 3 dice"));
+  }
+
+  #[test]
+  fn points_at_the_problem() {
+    assert_eq!(parse_error("1d0").position, 2);
+    assert_eq!(parse_error("2d6+").position, 4);
+    assert_eq!(parse_error("(1d6").position, 4);
+    assert_eq!(parse_error("2d6 ? 3").position, 4);
+  }
+
+  #[test]
+  fn renders_an_arrow_under_the_problem() {
+    let input = "1d0";
+
+    assert_eq!(
+      parse_error(input).render(input),
+      "**I will not roll a d0**\n```\n1d0\n  ^\n```"
+    );
+  }
+
+  fn distribution(input: &str) -> Distribution {
+    parse(input)
+      .expect("Expected it to parse")
+      .distribution()
+      .expect("Expected to work out the odds")
+  }
+
+  fn assert_close(actual: f64, expected: f64) {
+    assert!(
+      (actual - expected).abs() < 1e-6,
+      "Expected {}, got {}",
+      expected,
+      actual
+    );
+  }
+
+  #[test]
+  fn works_out_plain_dice() {
+    let odds = distribution("2d6");
+
+    assert_close(odds.mean(), 7.0);
+    assert_close(odds.chances[&7], 6.0 / 36.0);
+    assert_eq!((odds.min(), odds.max()), (2, 12));
+  }
+
+  #[test]
+  fn works_out_rerolls() {
+    // Rerolling until it isn't a 1 is the same as rolling a d5, shifted up
+    let odds = distribution("1d6r1");
+
+    assert_close(odds.mean(), 4.0);
+    assert_close(odds.at_least(2), 1.0);
+    assert_eq!(odds.max(), 6);
+
+    // Rerolling once can still land on a 1
+    let odds = distribution("1d6ro1");
+
+    assert_close(odds.mean(), 47.0 / 12.0);
+    assert_close(odds.chances[&1], 1.0 / 36.0);
+    assert_eq!((odds.min(), odds.max()), (1, 6));
+  }
+
+  #[test]
+  fn works_out_kept_dice() {
+    let odds = distribution("4d6kh3");
+
+    assert_close(odds.mean(), 15869.0 / 1296.0);
+    assert_close(odds.chances[&18], 21.0 / 1296.0);
+    assert_eq!((odds.min(), odds.max()), (3, 18));
+  }
+
+  #[test]
+  fn works_out_exploding_dice() {
+    let odds = distribution("1d6!");
+
+    // Each roll adds 3.5 on average, and there are 6/5 rolls on average
+    assert_close(odds.mean(), 4.2);
+    assert_eq!(odds.min(), 1);
+    assert!(odds.max() > 6);
+    // A 6 always explodes, so it can't be the total
+    assert!(!odds.chances.contains_key(&6));
+  }
+
+  #[test]
+  fn handles_totals_near_the_limits() {
+    assert!(parse("1d6*4294967295*4294967295")
+      .unwrap()
+      .distribution()
+      .is_err());
+
+    // Too far apart to subtract, but each fits
+    let odds = distribution("(1d2*2-3)*4294967295*2147483647");
+    let most = 4294967295 * 2147483647;
+    assert_eq!((odds.min(), odds.max()), (-most, most));
+  }
+}
@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use redis::{aio::Connection, cmd, RedisResult, Script};
+
+use super::rng::random_id;
+
+pub const LEASE_KEY: &str = "safety:leader";
+
+/// How long a lease is valid without being renewed
+pub const LEASE_MS: usize = 30_000;
+
+/// How often the holder (or a follower) should call `refresh`.
+/// This must be comfortably smaller than `LEASE_MS`
+pub const LEASE_RENEW_SECS: u64 = 10;
+
+lazy_static! {
+  // Only extend the lease if we still own it; otherwise another instance has
+  // taken over and we must not clobber its token
+  static ref RENEW_SCRIPT: Script = Script::new(
+    r"
+    if redis.call('GET', KEYS[1]) == ARGV[1] then
+      return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    else
+      return 0
+    end"
+  );
+}
+
+/// A Redis-backed lease used to make sure only one running instance of the bot
+/// handles periodic work (scheduled polls, birthdays, briefings).
+///
+/// Every instance calls `refresh` periodically. The instance holding the lease
+/// renews it; every other instance tries to grab it with `SET NX PX`, which
+/// only succeeds once the holder has stopped renewing (i.e. died).
+///
+/// `refresh` should be given a connection nothing else uses, so a renewal is
+/// never stuck waiting behind other work while the lease runs out
+pub struct Lease {
+  token: String,
+  leader: AtomicBool,
+  /// When (in milliseconds) the lease runs out if it isn't renewed
+  expires_at: AtomicI64,
+}
+
+impl Lease {
+  pub fn new() -> Lease {
+    Lease {
+      token: format!("{}:{}", std::process::id(), random_id()),
+      leader: AtomicBool::new(false),
+      expires_at: AtomicI64::new(0),
+    }
+  }
+
+  /// Whether this instance holds the lease. This is false once the lease has
+  /// run out, even if the last renewal hasn't been noticed to fail yet, so
+  /// check it right before doing anything only the leader may do
+  #[inline]
+  pub fn is_leader(&self) -> bool {
+    self.leader.load(Ordering::Relaxed)
+      && Utc::now().timestamp_millis() < self.expires_at.load(Ordering::Relaxed)
+  }
+
+  /// Renews the lease if we hold it, or attempts to acquire it otherwise.
+  /// Returns whether this instance is the leader afterwards.
+  /// On a Redis error, leadership is dropped so two instances never both
+  /// believe they are the leader
+  pub async fn refresh(&self, con: &mut Connection) -> RedisResult<bool> {
+    // The lease runs from when it was asked for, not from when Redis answered
+    let started = Utc::now().timestamp_millis();
+    let result = self.try_refresh(con).await;
+
+    let leader = *result.as_ref().unwrap_or(&false);
+
+    if leader {
+      self
+        .expires_at
+        .store(started + LEASE_MS as i64, Ordering::Relaxed);
+    }
+
+    let was_leader = self.leader.swap(leader, Ordering::Relaxed);
+
+    if leader != was_leader {
+      println!(
+        "Instance {} is {} the leader",
+        self.token,
+        if leader { "now" } else { "no longer" }
+      );
+    }
+
+    result
+  }
+
+  async fn try_refresh(&self, con: &mut Connection) -> RedisResult<bool> {
+    if self.leader.load(Ordering::Relaxed) {
+      let renewed: i64 = RENEW_SCRIPT
+        .key(LEASE_KEY)
+        .arg(&self.token)
+        .arg(LEASE_MS)
+        .invoke_async(con)
+        .await?;
+
+      if renewed == 1 {
+        return Ok(true);
+      }
+    }
+
+    let acquired: Option<String> = cmd("SET")
+      .arg(LEASE_KEY)
+      .arg(&self.token)
+      .arg("NX")
+      .arg("PX")
+      .arg(LEASE_MS)
+      .query_async(con)
+      .await?;
+
+    Ok(acquired.is_some())
+  }
+}
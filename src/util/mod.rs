@@ -1,4 +1,7 @@
 #![macro_use]
 
+pub mod checkpoint;
+pub mod dice;
+pub mod leader;
 pub mod rng;
 pub mod scheduler;
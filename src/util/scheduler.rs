@@ -162,6 +162,29 @@ impl Callable<Arc<Http>> for Poll {
   }
 }
 
+/// A role to take away from someone later (e.g. a birthday role)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RoleRemoval {
+  pub guild: u64,
+  pub user: u64,
+  pub role: u64,
+}
+
+#[async_trait]
+impl Callable<Arc<Http>> for RoleRemoval {
+  async fn call(&self, http: &Arc<Http>) {
+    if let Err(error) = http
+      .remove_member_role(self.guild, self.user, self.role, Some("Scheduled removal"))
+      .await
+    {
+      println!(
+        "Could not remove role {} from {} in {}: {:?}",
+        self.role, self.user, self.guild, error
+      );
+    }
+  }
+}
+
 pub struct Scheduler {
   connection: Connection,
 }
@@ -190,6 +213,8 @@ impl FromRedisValue for MyVec {
 const IDS_KEY: &str = "ids";
 const JOBS_KEY: &str = "jobs";
 const SCHEDULE_KEY: &str = "schedule";
+const ROLE_JOBS_KEY: &str = "role_jobs";
+const ROLE_SCHEDULE_KEY: &str = "role_schedule";
 
 impl Scheduler {
   pub fn new(connection: Connection) -> Scheduler {
@@ -292,6 +317,47 @@ impl Scheduler {
     Ok(jobs_as_t)
   }
 
+  pub async fn get_and_clear_ready_role_removals(
+    &mut self,
+    timestamp: i64,
+  ) -> RedisResult<Vec<RoleRemoval>> {
+    let con = &mut self.connection;
+
+    let jobs_as_string: Vec<MyVec> =
+      async_transaction!(con, &[ROLE_JOBS_KEY, ROLE_SCHEDULE_KEY], {
+        let ready_jobs: Vec<String> = con
+          .zrangebyscore(ROLE_SCHEDULE_KEY, "-inf", timestamp)
+          .await?;
+
+        if ready_jobs.is_empty() {
+          Some(vec![])
+        } else {
+          pipe()
+            .atomic()
+            .hget(ROLE_JOBS_KEY, &ready_jobs[..])
+            .hdel(ROLE_JOBS_KEY, &ready_jobs[..])
+            .ignore()
+            .zrembyscore(ROLE_SCHEDULE_KEY, "-inf", timestamp)
+            .ignore()
+            .query_async(con)
+            .await?
+        }
+      });
+
+    let mut jobs_as_t: Vec<RoleRemoval> = Vec::new();
+
+    for my_vec in jobs_as_string.iter() {
+      for job in my_vec.v.iter() {
+        match bincode::deserialize(job) {
+          Ok(result) => jobs_as_t.push(result),
+          Err(error) => return Err(RedisError::from(Error::new(Other, error))),
+        }
+      }
+    }
+
+    Ok(jobs_as_t)
+  }
+
   // pub async fn get_ready_jobs(&mut self, timestamp: i64) -> RedisResult<Vec<Poll>> {
   //   let con = &mut self.connection;
 
@@ -399,6 +465,31 @@ impl Scheduler {
     Ok(new_id)
   }
 
+  /// Schedules a role to be removed at `timestamp`. Scheduling the same role
+  /// for the same user again replaces the earlier removal
+  pub async fn schedule_role_removal(
+    &mut self,
+    removal: &RoleRemoval,
+    timestamp: i64,
+  ) -> RedisResult<()> {
+    let task_id = format!("{}:{}:{}", removal.guild, removal.user, removal.role);
+
+    let task = match bincode::serialize(removal) {
+      Ok(serialized) => serialized,
+      Err(error) => return redis_error!(error),
+    };
+    let con = &mut self.connection;
+
+    pipe()
+      .atomic()
+      .zadd(ROLE_SCHEDULE_KEY, &task_id, timestamp)
+      .hset(ROLE_JOBS_KEY, &task_id, &task[..])
+      .query_async::<_, ()>(con)
+      .await?;
+
+    Ok(())
+  }
+
   pub async fn schedule_job(
     &mut self,
     task: &Poll,